@@ -0,0 +1,461 @@
+//! Offline breach check against a local copy of the Have I Been Pwned
+//! Pwned Passwords list. Nothing is sent over the network: the list is
+//! downloaded separately, as one `HASH:COUNT` file or as a directory of
+//! range files named by their five character hash prefix, and turned into
+//! a sorted index that is binary searched on disk.
+//!
+//! Index layout, all integers little-endian:
+//! ```text
+//! [magic "RPBI" (4 bytes)] + [version (1 byte)] + [hash kind (1 byte)] + [reserved (2 bytes)] + [record count (8 bytes)]
+//! followed by records sorted by hash: [hash (20 bytes SHA-1, 16 bytes NTLM)] + [breach count (4 bytes)]
+//! ```
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use md4::Md4;
+use sha1::{Digest, Sha1};
+
+use crate::entry::LoadedEntry;
+use crate::error::Error;
+
+const MAGIC: &[u8; 4] = b"RPBI";
+const VERSION: u8 = 1;
+const HEADER_LEN: u64 = 16;
+const COUNT_LEN: usize = 4;
+/// Range files are named by the first five hex digits of their hashes.
+const PREFIX_LEN: usize = 5;
+/// Records sorted in memory at a time when a list is not ordered by hash,
+/// about 100 MB of SHA-1 records.
+const SORT_CHUNK_RECORDS: usize = 1 << 22;
+
+/// Hash function a breach list is keyed by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HashKind {
+    Sha1,
+    /// MD4 of the UTF-16LE password, as used by Windows.
+    Ntlm,
+}
+
+impl HashKind {
+    fn id(self) -> u8 {
+        match self {
+            HashKind::Sha1 => 1,
+            HashKind::Ntlm => 2,
+        }
+    }
+
+    fn from_id(id: u8) -> Result<Self, Error> {
+        match id {
+            1 => Ok(HashKind::Sha1),
+            2 => Ok(HashKind::Ntlm),
+            id => Err(Error::UnknownFormat(format!("breach index hash id {}", id))),
+        }
+    }
+
+    /// Length of a hash in bytes.
+    pub fn hash_len(self) -> usize {
+        match self {
+            HashKind::Sha1 => 20,
+            HashKind::Ntlm => 16,
+        }
+    }
+
+    fn from_hex_len(len: usize) -> Option<Self> {
+        [HashKind::Sha1, HashKind::Ntlm].into_iter().find(|kind| kind.hash_len() * 2 == len)
+    }
+
+    pub fn hash(self, password: &str) -> Vec<u8> {
+        match self {
+            HashKind::Sha1 => Sha1::digest(password.as_bytes()).to_vec(),
+            HashKind::Ntlm => {
+                let utf16: Vec<u8> = password.encode_utf16().flat_map(u16::to_le_bytes).collect();
+                Md4::digest(&utf16).to_vec()
+            }
+        }
+    }
+}
+
+impl fmt::Display for HashKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            HashKind::Sha1 => "SHA-1",
+            HashKind::Ntlm => "NTLM",
+        })
+    }
+}
+
+/// Default location of the index, next to the named vaults.
+pub fn default_index_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("rustpass").join("pwned-passwords.idx"))
+}
+
+/// An opened breach index.
+pub struct BreachIndex {
+    file: File,
+    kind: HashKind,
+    records: u64,
+}
+
+impl BreachIndex {
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let mut file = File::open(path)?;
+        let mut header = [0u8; HEADER_LEN as usize];
+        file.read_exact(&mut header).map_err(|_| Error::Corrupted("breach index truncated".to_string()))?;
+        if &header[..4] != MAGIC {
+            return Err(Error::UnknownFormat(format!("{} is not a breach index", path.display())));
+        }
+        if header[4] != VERSION {
+            return Err(Error::UnknownFormat(format!("breach index version {}", header[4])));
+        }
+        let kind = HashKind::from_id(header[5])?;
+        let records = u64::from_le_bytes(header[8..16].try_into().expect("8 bytes"));
+        let expected = records
+            .checked_mul((kind.hash_len() + COUNT_LEN) as u64)
+            .and_then(|len| len.checked_add(HEADER_LEN))
+            .ok_or_else(|| Error::Corrupted(format!("breach index record count {} is too large", records)))?;
+        if file.metadata()?.len() != expected {
+            return Err(Error::Corrupted("breach index truncated".to_string()));
+        }
+        Ok(BreachIndex { file, kind, records })
+    }
+
+    pub fn kind(&self) -> HashKind {
+        self.kind
+    }
+
+    /// Number of hashes in the index.
+    pub fn records(&self) -> u64 {
+        self.records
+    }
+
+    /// Returns how often the password with this hash has been seen in
+    /// breaches, or `None` if it has not.
+    pub fn lookup(&mut self, hash: &[u8]) -> Result<Option<u32>, Error> {
+        let record_len = self.kind.hash_len() + COUNT_LEN;
+        let mut record = vec![0u8; record_len];
+        let (mut low, mut high) = (0, self.records);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            self.file.seek(SeekFrom::Start(HEADER_LEN + middle * record_len as u64))?;
+            self.file.read_exact(&mut record)?;
+            match record[..self.kind.hash_len()].cmp(hash) {
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+                std::cmp::Ordering::Equal => {
+                    return Ok(Some(u32::from_le_bytes(record[self.kind.hash_len()..].try_into().expect("4 bytes"))));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Returns how often `password` has been seen in breaches, if at all.
+    pub fn check(&mut self, password: &str) -> Result<Option<u32>, Error> {
+        let hash = self.kind.hash(password);
+        self.lookup(&hash)
+    }
+}
+
+/// Looks up the password of every readable entry. Returns how often each
+/// breached one has been seen, by entry ID.
+pub fn breached_entries(index: &mut BreachIndex, entries: &[LoadedEntry]) -> Result<HashMap<String, u32>, Error> {
+    let mut breached = HashMap::new();
+    for (id, entry) in entries {
+        let Ok(entry) = entry else { continue };
+        if let Some(count) = index.check(entry.password.as_str())? {
+            breached.insert(id.clone(), count);
+        }
+    }
+    Ok(breached)
+}
+
+/// Like [`breached_entries`], with the index at `index`. Returns no
+/// breaches if no index has been built there.
+pub fn find_breaches(index: Option<&Path>, entries: &[LoadedEntry]) -> Result<HashMap<String, u32>, Error> {
+    match index.filter(|path| path.exists()) {
+        Some(path) => breached_entries(&mut BreachIndex::open(path)?, entries),
+        None => Ok(HashMap::new()),
+    }
+}
+
+/// Files to read from `source`, in hash order, with the hash prefix their
+/// lines leave out.
+fn source_files(source: &Path) -> Result<Vec<(PathBuf, String)>, Error> {
+    let prefix_of = |path: &Path| {
+        let stem = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or_default();
+        if stem.len() == PREFIX_LEN && stem.bytes().all(|b| b.is_ascii_hexdigit()) { stem.to_ascii_uppercase() } else { String::new() }
+    };
+    if !source.is_dir() {
+        return Ok(vec![(source.to_path_buf(), prefix_of(source))]);
+    }
+    let mut files = Vec::new();
+    for dirent in fs::read_dir(source)? {
+        let path = dirent?.path();
+        let prefix = prefix_of(&path);
+        if path.is_file() && !prefix.is_empty() {
+            files.push((path, prefix));
+        }
+    }
+    if files.is_empty() {
+        return Err(Error::InvalidInput(format!("no range files such as 5BAA6.txt in {}", source.display())));
+    }
+    files.sort_by(|a, b| a.1.cmp(&b.1));
+    Ok(files)
+}
+
+fn write_header(file: &mut File, kind: HashKind, records: u64) -> Result<(), Error> {
+    let mut header = Vec::with_capacity(HEADER_LEN as usize);
+    header.extend_from_slice(MAGIC);
+    header.extend_from_slice(&[VERSION, kind.id(), 0, 0]);
+    header.extend_from_slice(&records.to_le_bytes());
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&header)?;
+    Ok(())
+}
+
+/// Builds an index at `output` from a downloaded breach list and returns
+/// its hash kind and the number of hashes in it. The hash kind is told apart by the length of
+/// the hashes. Lists ordered by hash, as published, are streamed; others
+/// are sorted on disk in chunks of bounded size.
+pub fn build_index(source: &Path, output: &Path) -> Result<(HashKind, u64), Error> {
+    build_index_in_chunks(source, output, SORT_CHUNK_RECORDS)
+}
+
+fn build_index_in_chunks(source: &Path, output: &Path, chunk_records: usize) -> Result<(HashKind, u64), Error> {
+    let partial = output.with_extension("partial");
+    let runs = output.with_extension("runs");
+    let mut file = fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&partial)?;
+    let built = write_records(source, &mut file).and_then(|(kind, records, sorted)| {
+        if sorted {
+            write_header(&mut file, kind, records)?;
+            return Ok((kind, records));
+        }
+        sort_records(&mut file, &runs, kind, records, chunk_records)
+    });
+    drop(file);
+    let _ = fs::remove_file(&runs);
+    match built {
+        Ok(built) => {
+            fs::rename(&partial, output)?;
+            Ok(built)
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&partial);
+            Err(e)
+        }
+    }
+}
+
+/// Appends every record of `source` after the header. Returns the hash
+/// kind, the number of records and whether they were strictly ascending.
+fn write_records(source: &Path, file: &mut File) -> Result<(HashKind, u64, bool), Error> {
+    file.write_all(&[0u8; HEADER_LEN as usize])?;
+    let mut writer = BufWriter::new(file);
+    let mut kind = None;
+    let mut last: Vec<u8> = Vec::new();
+    let (mut records, mut sorted) = (0u64, true);
+    for (path, prefix) in source_files(source)? {
+        let reader = BufReader::new(File::open(&path)?);
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = || Error::InvalidInput(format!("{} line {}: expected HASH:COUNT", path.display(), number + 1));
+            let (hex, count) = line.split_once(':').unwrap_or((line, "1"));
+            let hex = format!("{}{}", prefix, hex);
+            let line_kind = HashKind::from_hex_len(hex.len()).ok_or_else(invalid)?;
+            if *kind.get_or_insert(line_kind) != line_kind {
+                return Err(Error::InvalidInput(format!("{} line {}: SHA-1 and NTLM hashes are mixed", path.display(), number + 1)));
+            }
+            let hash = hex::decode(&hex).map_err(|_| invalid())?;
+            let count: u32 = count.trim().parse::<u64>().map_err(|_| invalid())?.try_into().unwrap_or(u32::MAX);
+            sorted &= hash > last;
+            writer.write_all(&hash)?;
+            writer.write_all(&count.to_le_bytes())?;
+            last = hash;
+            records += 1;
+        }
+    }
+    writer.flush()?;
+    let kind = kind.ok_or_else(|| Error::InvalidInput(format!("no hashes found in {}", source.display())))?;
+    Ok((kind, records, sorted))
+}
+
+/// Sorts the records written by `write_records`, adding up the counts of
+/// repeated hashes. Chunks of `chunk_records` are sorted in memory and
+/// written one after another to the `runs` file, then merged back.
+fn sort_records(file: &mut File, runs: &Path, kind: HashKind, records: u64, chunk_records: usize) -> Result<(HashKind, u64), Error> {
+    let hash_len = kind.hash_len();
+    let record_len = hash_len + COUNT_LEN;
+
+    let mut runs_file = fs::OpenOptions::new().read(true).write(true).create(true).truncate(true).open(runs)?;
+    let mut run_bounds = Vec::new();
+    file.seek(SeekFrom::Start(HEADER_LEN))?;
+    let mut reader = BufReader::new(&mut *file);
+    let mut writer = BufWriter::new(&mut runs_file);
+    let mut start = 0u64;
+    while start < records {
+        let len = (records - start).min(chunk_records as u64);
+        let mut chunk = vec![0u8; len as usize * record_len];
+        reader.read_exact(&mut chunk)?;
+        let mut sorted: Vec<&[u8]> = chunk.chunks_exact(record_len).collect();
+        sorted.sort_unstable_by(|a, b| a[..hash_len].cmp(&b[..hash_len]));
+        for record in sorted {
+            writer.write_all(record)?;
+        }
+        run_bounds.push((start, len));
+        start += len;
+    }
+    writer.flush()?;
+    drop(writer);
+    drop(reader);
+
+    // Merge the runs, keeping the next record of each in a min-heap.
+    let mut readers = Vec::with_capacity(run_bounds.len());
+    for (start, len) in run_bounds {
+        let mut run = File::open(runs)?;
+        run.seek(SeekFrom::Start(start * record_len as u64))?;
+        readers.push((BufReader::new(run), len));
+    }
+    let run_count = readers.len();
+    let mut next_record = |run: usize| -> Result<Option<Vec<u8>>, Error> {
+        let (reader, left) = &mut readers[run];
+        if *left == 0 {
+            return Ok(None);
+        }
+        let mut record = vec![0u8; record_len];
+        reader.read_exact(&mut record)?;
+        *left -= 1;
+        Ok(Some(record))
+    };
+    let mut heap = BinaryHeap::new();
+    for run in 0..run_count {
+        if let Some(record) = next_record(run)? {
+            heap.push(Reverse((record, run)));
+        }
+    }
+
+    file.set_len(HEADER_LEN)?;
+    file.seek(SeekFrom::Start(HEADER_LEN))?;
+    let mut writer = BufWriter::new(&mut *file);
+    let mut merged = 0u64;
+    let mut current: Option<(Vec<u8>, u32)> = None;
+    while let Some(Reverse((record, run))) = heap.pop() {
+        if let Some(next) = next_record(run)? {
+            heap.push(Reverse((next, run)));
+        }
+        let (hash, count) = record.split_at(hash_len);
+        let count = u32::from_le_bytes(count.try_into().expect("4 bytes"));
+        match &mut current {
+            Some((last, total)) if last[..] == *hash => *total = total.saturating_add(count),
+            _ => {
+                if let Some((last, total)) = current.replace((hash.to_vec(), count)) {
+                    writer.write_all(&last)?;
+                    writer.write_all(&total.to_le_bytes())?;
+                    merged += 1;
+                }
+            }
+        }
+    }
+    if let Some((last, total)) = current {
+        writer.write_all(&last)?;
+        writer.write_all(&total.to_le_bytes())?;
+        merged += 1;
+    }
+    writer.flush()?;
+    drop(writer);
+    write_header(file, kind, merged)?;
+    Ok((kind, merged))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSWORD_SHA1: &str = "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8";
+    const PASSWORD_NTLM: &str = "8846F7EAEE8FB117AD06BDD830B7586C";
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rustpass-breach-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("temp dir");
+        dir
+    }
+
+    #[test]
+    fn test_hashes() {
+        assert_eq!(hex::encode_upper(HashKind::Sha1.hash("password")), PASSWORD_SHA1);
+        assert_eq!(hex::encode_upper(HashKind::Ntlm.hash("password")), PASSWORD_NTLM);
+    }
+
+    #[test]
+    fn test_range_files() -> Result<(), Error> {
+        let dir = scratch("ranges");
+        let ranges = dir.join("ranges");
+        fs::create_dir(&ranges)?;
+        fs::write(ranges.join("5BAA6.txt"), format!("0018A45C4D1DEF81644B54AB7F969B88D65:1\r\n{}:9659365\r\n", &PASSWORD_SHA1[5..]))?;
+        fs::write(ranges.join("00000.txt"), "0005AD76BD555C1D6D771DE417A4B87E4B4:10\n")?;
+        fs::write(ranges.join("README"), "not a range file")?;
+
+        let index = dir.join("sha1.idx");
+        assert_eq!(build_index(&ranges, &index)?, (HashKind::Sha1, 3));
+        let mut index = BreachIndex::open(&index)?;
+        assert_eq!(index.kind(), HashKind::Sha1);
+        assert_eq!(index.check("password")?, Some(9_659_365));
+        assert_eq!(index.check("correct horse battery staple")?, None);
+        assert_eq!(index.lookup(&hex::decode("000000005AD76BD555C1D6D771DE417A4B87E4B4").expect("hex"))?, Some(10));
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_unsorted_ntlm_file() -> Result<(), Error> {
+        let dir = scratch("ntlm");
+        let list = dir.join("pwned-passwords-ntlm.txt");
+        fs::write(&list, format!("FFFF0000FFFF0000FFFF0000FFFF0000:3\n{}:2\n0000FFFF0000FFFF0000FFFF0000FFFF:1\n{}:5\n", PASSWORD_NTLM, PASSWORD_NTLM.to_lowercase()))?;
+        let index = dir.join("ntlm.idx");
+        assert_eq!(build_index(&list, &index)?, (HashKind::Ntlm, 3));
+        // Two records per chunk puts the repeated hash in different runs.
+        assert_eq!(build_index_in_chunks(&list, &index, 2)?, (HashKind::Ntlm, 3));
+        assert!(!dir.join("ntlm.runs").exists());
+        let mut index = BreachIndex::open(&index)?;
+        assert_eq!(index.check("password")?, Some(7));
+        assert_eq!(index.check("Password")?, None);
+        assert_eq!(index.lookup(&[0xff; 16])?, None);
+
+        fs::write(&list, format!("{}:1\n{}:1\n", PASSWORD_NTLM, PASSWORD_SHA1))?;
+        assert!(matches!(build_index(&list, &dir.join("mixed.idx")), Err(Error::InvalidInput(_))));
+        assert!(!dir.join("mixed.partial").exists() && !dir.join("mixed.idx").exists());
+        assert!(matches!(BreachIndex::open(&list), Err(Error::UnknownFormat(_))));
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_damaged_index() -> Result<(), Error> {
+        let dir = scratch("damaged");
+        let list = dir.join("list.txt");
+        fs::write(&list, format!("{}:2\n", PASSWORD_SHA1))?;
+        let index = dir.join("sha1.idx");
+        build_index(&list, &index)?;
+        let bytes = fs::read(&index)?;
+
+        fs::write(&index, &bytes[..bytes.len() - 1])?;
+        assert!(matches!(BreachIndex::open(&index), Err(Error::Corrupted(_))));
+        fs::write(&index, &bytes[..8])?;
+        assert!(matches!(BreachIndex::open(&index), Err(Error::Corrupted(_))));
+        let mut huge = bytes.clone();
+        huge[8..16].copy_from_slice(&u64::MAX.to_le_bytes());
+        fs::write(&index, &huge)?;
+        assert!(matches!(BreachIndex::open(&index), Err(Error::Corrupted(_))));
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
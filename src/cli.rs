@@ -0,0 +1,823 @@
+use std::io::{stdin, stderr, BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use clap::{Args, Parser, Subcommand};
+use sled::Db;
+use zeroize::Zeroizing;
+use crossterm::{
+    event::{self, Event, KeyCode, KeyEventKind, KeyModifiers},
+    terminal::{disable_raw_mode, enable_raw_mode},
+};
+
+use crate::breach;
+use crate::config::Config;
+use crate::encrypt_decrypt::{self, Cipher, KdfParams, MasterKey};
+use crate::entry;
+use crate::generator::{self, Policy};
+use crate::health;
+use crate::otp::{Kind, Otp};
+use crate::passphrase::{self, Capitalize, PassphrasePolicy};
+use crate::recovery::{self, RecoveryKey};
+use crate::shamir::{self, Share};
+use crate::strength;
+use crate::vaults::Vaults;
+use crate::secret::SecretString;
+
+#[derive(Parser)]
+#[command(name = "rustpass", version, about)]
+pub struct Cli {
+    #[command(flatten)]
+    pub global: GlobalArgs,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Options shared by the TUI and every command.
+#[derive(Args)]
+pub struct GlobalArgs {
+    /// Key file required, together with the master password, to unlock the vault
+    #[arg(long, global = true, value_name = "PATH")]
+    pub keyfile: Option<PathBuf>,
+    /// Vault database to open (overrides RUSTPASS_VAULT and the config file)
+    #[arg(long, global = true, value_name = "PATH")]
+    pub vault: Option<PathBuf>,
+    /// Named vault to open instead of the last used one
+    #[arg(long, global = true, value_name = "NAME", conflicts_with = "vault")]
+    pub name: Option<String>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Create, list, rename, delete or switch between named vaults
+    Vault {
+        #[command(subcommand)]
+        action: VaultAction,
+    },
+    /// Change the master password of the vault
+    ChangePassword {
+        /// Key file to require from now on (defaults to the current --keyfile)
+        #[arg(long, value_name = "PATH", conflicts_with = "no_keyfile")]
+        new_keyfile: Option<PathBuf>,
+        /// Stop requiring a key file
+        #[arg(long)]
+        no_keyfile: bool,
+    },
+    /// Rewrite the vault key and all entries with the current format and KDF parameters
+    Upgrade,
+    /// Benchmark this machine and suggest Argon2 parameters for a target unlock time
+    Calibrate {
+        /// Target time for one key derivation, in milliseconds
+        #[arg(long, default_value_t = 500)]
+        target_ms: u64,
+        /// Argon2 parallelism (lanes)
+        #[arg(long, default_value_t = 1)]
+        parallelism: u32,
+        /// Apply the suggested parameters to the vault
+        #[arg(long)]
+        apply: bool,
+    },
+    /// Set the Argon2 parameters protecting the vault
+    SetKdf {
+        /// Memory cost in KiB
+        #[arg(long)]
+        memory: u32,
+        /// Time cost (iterations)
+        #[arg(long)]
+        time: u32,
+        /// Parallelism (lanes)
+        #[arg(long, default_value_t = 1)]
+        parallelism: u32,
+    },
+    /// Re-encrypt the vault key and every entry with another cipher
+    SetCipher {
+        /// aes256gcm or xchacha20poly1305
+        cipher: Cipher,
+    },
+    /// Manage the printable recovery key that can unlock the vault without the master password
+    RecoveryKey {
+        #[command(subcommand)]
+        action: RecoveryKeyAction,
+    },
+    /// Split the vault key into shares, any THRESHOLD of which can recover the vault
+    SplitKey {
+        /// Number of shares needed to recover the vault
+        #[arg(long)]
+        threshold: u8,
+        /// Number of shares to create
+        #[arg(long)]
+        shares: u8,
+    },
+    /// Unlock the vault with its recovery key, or with key shares, and set a new master password
+    Recover {
+        /// Rebuild the vault key from shares made by `split-key` instead
+        #[arg(long)]
+        shares: bool,
+    },
+    /// List the previous passwords of an entry, newest first
+    History {
+        /// Title of the entry
+        title: String,
+        /// Print the passwords too
+        #[arg(long)]
+        show: bool,
+    },
+    /// Make a previous password of an entry the current one again
+    Restore {
+        /// Title of the entry
+        title: String,
+        /// Version number shown by `history`, 1 being the newest
+        version: usize,
+    },
+    /// Print the current one-time code of an entry
+    Otp {
+        /// Title of the entry
+        title: String,
+    },
+    /// Generate random passwords, optionally from or into a saved profile
+    Generate(GenerateArgs),
+    /// Generate memorable passphrases of random words
+    Passphrase(PassphraseArgs),
+    /// Check entry passwords against a local copy of the Have I Been Pwned password list
+    Breach {
+        #[command(subcommand)]
+        action: BreachAction,
+    },
+    /// Report breached, reused, weak, old and empty passwords
+    Health {
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+        /// Report passwords not changed in this many days (defaults to max_password_age_days in the config file, or 365)
+        #[arg(long, value_name = "DAYS")]
+        max_age: Option<u64>,
+    },
+    /// List or delete the vault's saved generator profiles
+    Profile {
+        #[command(subcommand)]
+        action: ProfileAction,
+    },
+}
+
+/// Generator policy options. Each one overrides the profile in use, or the
+/// built in policy when no profile is given.
+#[derive(Args)]
+pub struct GenerateArgs {
+    /// Number of characters
+    #[arg(long, short)]
+    pub length: Option<usize>,
+    /// Leave out lowercase letters
+    #[arg(long)]
+    pub no_lowercase: bool,
+    /// Leave out uppercase letters
+    #[arg(long)]
+    pub no_uppercase: bool,
+    /// Leave out digits
+    #[arg(long)]
+    pub no_digits: bool,
+    /// Leave out symbols
+    #[arg(long, conflicts_with = "symbols")]
+    pub no_symbols: bool,
+    /// Symbols to draw from instead of the default set
+    #[arg(long, value_name = "SET", allow_hyphen_values = true)]
+    pub symbols: Option<String>,
+    /// Leave out characters that are easily confused, such as l, 1 and I
+    #[arg(long)]
+    pub exclude_ambiguous: bool,
+    /// Further characters to leave out
+    #[arg(long, value_name = "CHARS", allow_hyphen_values = true)]
+    pub exclude: Option<String>,
+    /// Do not require one character of every class
+    #[arg(long)]
+    pub no_require_each: bool,
+    /// Start from this saved profile
+    #[arg(long, value_name = "NAME", conflicts_with = "site")]
+    pub profile: Option<String>,
+    /// Start from the profile matching this entry title or URL
+    #[arg(long, value_name = "TITLE_OR_URL")]
+    pub site: Option<String>,
+    /// Save the resulting policy as a profile of the vault
+    #[arg(long, value_name = "NAME")]
+    pub save: Option<String>,
+    /// Number of passwords to print
+    #[arg(long, short, default_value_t = 1)]
+    pub count: usize,
+}
+
+impl GenerateArgs {
+    /// Returns true if the vault has to be unlocked to read or save profiles.
+    pub fn uses_vault(&self) -> bool {
+        self.profile.is_some() || self.site.is_some() || self.save.is_some()
+    }
+
+    fn apply(&self, policy: &mut Policy) {
+        if let Some(length) = self.length {
+            policy.length = length;
+        }
+        policy.lowercase &= !self.no_lowercase;
+        policy.uppercase &= !self.no_uppercase;
+        policy.digits &= !self.no_digits;
+        if let Some(symbols) = &self.symbols {
+            policy.symbols = symbols.clone();
+        }
+        if self.no_symbols {
+            policy.symbols.clear();
+        }
+        policy.exclude_ambiguous |= self.exclude_ambiguous;
+        if let Some(exclude) = &self.exclude {
+            policy.exclude.push_str(exclude);
+        }
+        policy.require_each_class &= !self.no_require_each;
+    }
+}
+
+#[derive(Subcommand)]
+pub enum VaultAction {
+    /// List the named vaults, marking the last used one
+    List,
+    /// Create a vault and set its master password
+    Create {
+        name: String,
+        /// Cipher for the new vault
+        #[arg(long, default_value_t = Cipher::default())]
+        cipher: Cipher,
+    },
+    /// Rename a vault
+    Rename { old: String, new: String },
+    /// Delete a vault and every entry in it
+    Delete { name: String },
+    /// Open this vault by default from now on
+    Switch { name: String },
+}
+
+#[derive(Args)]
+pub struct PassphraseArgs {
+    /// Number of words
+    #[arg(long, short, default_value_t = 6)]
+    pub words: usize,
+    /// Text between the words
+    #[arg(long, short, default_value = "-", allow_hyphen_values = true)]
+    pub separator: String,
+    /// none, first, each or random (each word by a coin flip)
+    #[arg(long, default_value_t = Capitalize::None)]
+    pub capitalize: Capitalize,
+    /// Number of words to append a random digit to
+    #[arg(long, default_value_t = 0)]
+    pub digits: usize,
+    /// Number of passphrases to print
+    #[arg(long, short, default_value_t = 1)]
+    pub count: usize,
+}
+
+#[derive(Subcommand)]
+pub enum BreachAction {
+    /// Build the breach index from a downloaded SHA-1 or NTLM hash list, or a directory of range files
+    Index {
+        source: PathBuf,
+        /// Where to write the index (defaults to breach_index in the config file, or the data directory)
+        #[arg(long, value_name = "PATH")]
+        index: Option<PathBuf>,
+    },
+    /// List the entries whose password appears in the breach index
+    Check {
+        /// Index to use instead of the default one
+        #[arg(long, value_name = "PATH")]
+        index: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ProfileAction {
+    /// List the saved profiles and their policies
+    List,
+    /// Delete a saved profile
+    Delete { profile: String },
+}
+
+#[derive(Subcommand)]
+pub enum RecoveryKeyAction {
+    /// Generate a new recovery key, replacing any previous one, and print it once
+    Generate,
+    /// Remove the recovery key
+    Revoke,
+}
+
+pub fn run(db: &Db, command: Command, global: &GlobalArgs, config: &Config) -> Result<(), anyhow::Error> {
+    match command {
+        Command::Vault { .. } => unreachable!("vault commands run without an open vault"),
+        Command::ChangePassword { new_keyfile, no_keyfile } => {
+            let new_keyfile = if no_keyfile { None } else { new_keyfile.as_deref().or(global.keyfile.as_deref()) };
+            change_password(db, global, new_keyfile, config.min_master_strength())
+        }
+        Command::Upgrade => upgrade(db, global),
+        Command::Calibrate { target_ms, parallelism, apply } => calibrate(db, target_ms, parallelism, apply, global),
+        Command::SetKdf { memory, time, parallelism } => set_kdf(db, KdfParams::new(memory, time, parallelism)?, global),
+        Command::SetCipher { cipher } => set_cipher(db, cipher, global),
+        Command::RecoveryKey { action } => recovery_key(db, action, global),
+        Command::SplitKey { threshold, shares } => split_key(db, threshold, shares, global),
+        Command::Recover { shares } => recover(db, shares, global, config.min_master_strength()),
+        Command::History { title, show } => history(db, &title, show, global),
+        Command::Restore { title, version } => restore(db, &title, version, global),
+        Command::Otp { title } => one_time_code(db, &title, global),
+        Command::Generate(args) => generate(Some(db), &args, global),
+        Command::Passphrase(args) => passphrase(&args),
+        Command::Profile { action } => profile(db, action, global),
+        Command::Breach { action: BreachAction::Index { source, index } } => build_breach_index(&source, index, config),
+        Command::Breach { action: BreachAction::Check { index } } => check_breaches(db, index, global, config),
+        Command::Health { json, max_age } => health(db, json, max_age.unwrap_or(config.max_password_age_days()), global, config),
+    }
+}
+
+pub fn run_vault(action: VaultAction, vaults: &Vaults, config: &mut Config, global: &GlobalArgs) -> Result<(), anyhow::Error> {
+    match action {
+        VaultAction::List => {
+            for name in vaults.list()? {
+                let marker = if config.last_vault.as_deref() == Some(name.as_str()) { "*" } else { " " };
+                println!("{} {}", marker, name);
+            }
+        }
+        VaultAction::Create { name, cipher } => {
+            let db = vaults.create(&name)?;
+            let created = prompt_new_master_key(global.keyfile.as_deref(), config.min_master_strength())
+                .and_then(|master| Ok(encrypt_decrypt::store_master_password(&db, &master, &KdfParams::default(), cipher)?));
+            if let Err(e) = created {
+                drop(db);
+                vaults.delete(&name)?;
+                return Err(e);
+            }
+            eprintln!("Vault {} created.", name);
+        }
+        VaultAction::Rename { old, new } => {
+            vaults.rename(&old, &new)?;
+            if config.last_vault.as_deref() == Some(old.as_str()) {
+                config.remember_vault(&new)?;
+            }
+            eprintln!("Vault {} renamed to {}.", old, new);
+        }
+        VaultAction::Delete { name } => {
+            if !vaults.exists(&name)? {
+                return Err(anyhow::anyhow!("No vault named {}", name));
+            }
+            eprint!("Delete vault {} and all its entries? Type its name to confirm: ", name);
+            let mut line = String::new();
+            stdin().lock().read_line(&mut line)?;
+            if line.trim() != name {
+                return Err(anyhow::anyhow!("Cancelled"));
+            }
+            vaults.delete(&name)?;
+            if config.last_vault.as_deref() == Some(name.as_str()) {
+                config.last_vault = None;
+                config.save()?;
+            }
+            eprintln!("Vault {} deleted.", name);
+        }
+        VaultAction::Switch { name } => {
+            if !vaults.exists(&name)? {
+                return Err(anyhow::anyhow!("No vault named {}", name));
+            }
+            config.remember_vault(&name)?;
+            eprintln!("Switched to vault {}.", name);
+        }
+    }
+    Ok(())
+}
+
+fn require_vault(db: &Db) -> Result<(), anyhow::Error> {
+    if !encrypt_decrypt::is_master_password_configured(db)? {
+        return Err(anyhow::anyhow!("No vault configured yet, run rustpass to create one"));
+    }
+    Ok(())
+}
+
+/// Prompts for the master password and combines it with the key file.
+fn prompt_master_key(db: &Db, prompt: &str, global: &GlobalArgs) -> Result<MasterKey, anyhow::Error> {
+    if global.keyfile.is_none() && encrypt_decrypt::requires_key_file(db)? {
+        return Err(anyhow::anyhow!("This vault requires a key file, pass --keyfile"));
+    }
+    let password = prompt_password(prompt)?;
+    Ok(MasterKey::new(password.as_bytes(), global.keyfile.as_deref())?)
+}
+
+/// Prompts twice for a new master password and combines it with `key_file`.
+/// Passwords scoring below `min_strength` are refused.
+fn prompt_new_master_key(key_file: Option<&Path>, min_strength: u8) -> Result<MasterKey, anyhow::Error> {
+    let new_password = prompt_password("New master password: ")?;
+    if new_password.is_empty() {
+        return Err(anyhow::anyhow!("Master password cannot be empty"));
+    }
+    let estimate = strength::estimate(new_password.as_str());
+    if estimate.score < min_strength {
+        let mut message = format!(
+            "Master password is {}, at least {} is required",
+            estimate.label(), strength::SCORE_NAMES[min_strength as usize]
+        );
+        for hint in estimate.warning.iter().chain(&estimate.suggestions) {
+            message.push_str(&format!("\n  {}", hint));
+        }
+        return Err(anyhow::anyhow!(message));
+    }
+    if prompt_password("Confirm new master password: ")? != new_password {
+        return Err(anyhow::anyhow!("Passwords do not match"));
+    }
+    Ok(MasterKey::new(new_password.as_bytes(), key_file)?)
+}
+
+fn change_password(db: &Db, global: &GlobalArgs, new_keyfile: Option<&Path>, min_strength: u8) -> Result<(), anyhow::Error> {
+    require_vault(db)?;
+    let old = prompt_master_key(db, "Current master password: ", global)?;
+    let new = prompt_new_master_key(new_keyfile, min_strength)?;
+    if !encrypt_decrypt::change_master_password(db, &old, &new)? {
+        return Err(anyhow::anyhow!("Wrong Password"));
+    }
+    eprintln!("Master password changed.");
+    Ok(())
+}
+
+fn upgrade(db: &Db, global: &GlobalArgs) -> Result<(), anyhow::Error> {
+    require_vault(db)?;
+    let master = prompt_master_key(db, "Master password: ", global)?;
+    match encrypt_decrypt::upgrade_vault(db, &master)? {
+        Some(upgraded) => eprintln!("Vault upgraded, {} entries rewritten.", upgraded),
+        None => return Err(anyhow::anyhow!("Wrong Password")),
+    }
+    Ok(())
+}
+
+fn calibrate(db: &Db, target_ms: u64, parallelism: u32, apply: bool, global: &GlobalArgs) -> Result<(), anyhow::Error> {
+    eprintln!("Calibrating Argon2id for {} ms...", target_ms);
+    let (params, elapsed) = encrypt_decrypt::calibrate(Duration::from_millis(target_ms), parallelism)?;
+    println!(
+        "memory = {} KiB, time = {}, parallelism = {} ({} ms)",
+        params.m_cost, params.t_cost, params.p_cost, elapsed.as_millis()
+    );
+    if apply {
+        set_kdf(db, params, global)?;
+    } else {
+        eprintln!(
+            "Run with --apply, or `rustpass set-kdf --memory {} --time {} --parallelism {}`, to use them.",
+            params.m_cost, params.t_cost, params.p_cost
+        );
+    }
+    Ok(())
+}
+
+fn set_kdf(db: &Db, params: KdfParams, global: &GlobalArgs) -> Result<(), anyhow::Error> {
+    require_vault(db)?;
+    let master = prompt_master_key(db, "Master password: ", global)?;
+    if !encrypt_decrypt::set_kdf_params(db, &master, &params)? {
+        return Err(anyhow::anyhow!("Wrong Password"));
+    }
+    eprintln!("Vault KDF parameters updated.");
+    Ok(())
+}
+
+fn set_cipher(db: &Db, cipher: Cipher, global: &GlobalArgs) -> Result<(), anyhow::Error> {
+    require_vault(db)?;
+    let master = prompt_master_key(db, "Master password: ", global)?;
+    match encrypt_decrypt::set_cipher(db, &master, cipher)? {
+        Some(rewritten) => eprintln!("Vault switched to {}, {} entries re-encrypted.", cipher, rewritten),
+        None => return Err(anyhow::anyhow!("Wrong Password")),
+    }
+    if recovery::has_recovery_key(db)? {
+        eprintln!("Run `rustpass recovery-key generate` to re-encrypt the recovery key with {} too.", cipher);
+    }
+    Ok(())
+}
+
+fn recovery_key(db: &Db, action: RecoveryKeyAction, global: &GlobalArgs) -> Result<(), anyhow::Error> {
+    require_vault(db)?;
+    let master = prompt_master_key(db, "Master password: ", global)?;
+    let Some(vault_key) = encrypt_decrypt::unlock(db, &master)? else {
+        return Err(anyhow::anyhow!("Wrong Password"));
+    };
+    match action {
+        RecoveryKeyAction::Generate => {
+            let recovery = recovery::regenerate_recovery_key(db, &vault_key)?;
+            println!("{}", recovery.display().as_str());
+            eprintln!("Write this key down and keep it somewhere safe. It will not be shown again.");
+        }
+        RecoveryKeyAction::Revoke => {
+            if !recovery::has_recovery_key(db)? {
+                return Err(anyhow::anyhow!("This vault has no recovery key"));
+            }
+            recovery::revoke_recovery_key(db)?;
+            eprintln!("Recovery key revoked.");
+        }
+    }
+    Ok(())
+}
+
+fn split_key(db: &Db, threshold: u8, count: u8, global: &GlobalArgs) -> Result<(), anyhow::Error> {
+    require_vault(db)?;
+    let master = prompt_master_key(db, "Master password: ", global)?;
+    let Some(vault_key) = encrypt_decrypt::unlock(db, &master)? else {
+        return Err(anyhow::anyhow!("Wrong Password"));
+    };
+    for share in shamir::split_vault_key(db, &vault_key, threshold, count)? {
+        println!("{}", share.encode().as_str());
+    }
+    eprintln!("Give one share to each holder. Any {} of them can recover the vault.", threshold);
+    Ok(())
+}
+
+/// Prompts for shares until as many as the first share's threshold are given.
+fn prompt_shares() -> Result<Vec<Share>, anyhow::Error> {
+    let mut shares = vec![Share::parse(prompt_password("Share 1: ")?.as_str())?];
+    while shares.len() < shares[0].threshold() as usize {
+        let prompt = format!("Share {} of {}: ", shares.len() + 1, shares[0].threshold());
+        shares.push(Share::parse(prompt_password(&prompt)?.as_str())?);
+    }
+    Ok(shares)
+}
+
+fn recover(db: &Db, use_shares: bool, global: &GlobalArgs, min_strength: u8) -> Result<(), anyhow::Error> {
+    require_vault(db)?;
+    if use_shares {
+        if !shamir::has_shares(db)? {
+            return Err(anyhow::anyhow!("This vault key has not been split into shares"));
+        }
+        let shares = prompt_shares()?;
+        let new = prompt_new_master_key(global.keyfile.as_deref(), min_strength)?;
+        if shamir::recover_vault(db, &shares, &new)?.is_none() {
+            return Err(anyhow::anyhow!("Shares do not match this vault"));
+        }
+    } else {
+        if !recovery::has_recovery_key(db)? {
+            return Err(anyhow::anyhow!("This vault has no recovery key"));
+        }
+        let recovery = RecoveryKey::parse(prompt_password("Recovery key: ")?.as_str())?;
+        let new = prompt_new_master_key(global.keyfile.as_deref(), min_strength)?;
+        if recovery::recover_vault(db, &recovery, &new)?.is_none() {
+            return Err(anyhow::anyhow!("Wrong recovery key"));
+        }
+    }
+    eprintln!("Vault recovered, new master password set.");
+    Ok(())
+}
+
+fn history(db: &Db, title: &str, show: bool, global: &GlobalArgs) -> Result<(), anyhow::Error> {
+    require_vault(db)?;
+    let master = prompt_master_key(db, "Master password: ", global)?;
+    let Some(vault_key) = encrypt_decrypt::unlock(db, &master)? else {
+        return Err(anyhow::anyhow!("Wrong Password"));
+    };
+    let Some((_, entry)) = entry::find_by_title(db, &vault_key, title)? else {
+        return Err(anyhow::anyhow!("No entry titled {}", title));
+    };
+    if entry.history.is_empty() {
+        eprintln!("{} has no previous passwords.", title);
+    }
+    for (i, version) in entry.history.iter().enumerate() {
+        let replaced = entry::format_timestamp(version.replaced);
+        if show {
+            println!("{}  replaced {}  {}", i + 1, replaced, version.password.as_str());
+        } else {
+            println!("{}  replaced {}", i + 1, replaced);
+        }
+    }
+    Ok(())
+}
+
+fn restore(db: &Db, title: &str, version: usize, global: &GlobalArgs) -> Result<(), anyhow::Error> {
+    require_vault(db)?;
+    if version == 0 {
+        return Err(anyhow::anyhow!("Versions are numbered from 1, see `rustpass history`"));
+    }
+    let master = prompt_master_key(db, "Master password: ", global)?;
+    let Some(vault_key) = encrypt_decrypt::unlock(db, &master)? else {
+        return Err(anyhow::anyhow!("Wrong Password"));
+    };
+    let Some((id, _)) = entry::find_by_title(db, &vault_key, title)? else {
+        return Err(anyhow::anyhow!("No entry titled {}", title));
+    };
+    entry::restore_password(db, &vault_key, &id, version - 1)?;
+    eprintln!("Password version {} of {} restored; the replaced password was added to its history.", version, title);
+    Ok(())
+}
+
+/// Prints an entry's one-time code. An HOTP seed is moved on to the next
+/// code, as the printed one counts as used.
+fn one_time_code(db: &Db, title: &str, global: &GlobalArgs) -> Result<(), anyhow::Error> {
+    require_vault(db)?;
+    let master = prompt_master_key(db, "Master password: ", global)?;
+    let Some(vault_key) = encrypt_decrypt::unlock(db, &master)? else {
+        return Err(anyhow::anyhow!("Wrong Password"));
+    };
+    let Some((id, mut entry)) = entry::find_by_title(db, &vault_key, title)? else {
+        return Err(anyhow::anyhow!("No entry titled {}", title));
+    };
+    if entry.otp.is_empty() {
+        return Err(anyhow::anyhow!("{} has no one-time password", title));
+    }
+    let mut otp = Otp::parse(entry.otp.as_str())?;
+    let now = entry::now();
+    println!("{}", otp.code(now).as_str());
+    match otp.kind {
+        Kind::Totp { .. } => {
+            if let Some(remaining) = otp.remaining(now) {
+                eprintln!("Valid for {} more seconds.", remaining);
+            }
+        }
+        Kind::Hotp { .. } => {
+            otp.advance();
+            entry.otp = otp.to_uri();
+            entry::save_entry(db, &vault_key, Some(&id), &mut entry)?;
+        }
+    }
+    Ok(())
+}
+
+/// Prints generated passwords. `db` is only needed, and the vault only
+/// unlocked, when profiles are read or saved.
+pub fn generate(db: Option<&Db>, args: &GenerateArgs, global: &GlobalArgs) -> Result<(), anyhow::Error> {
+    if args.count == 0 {
+        return Err(anyhow::anyhow!("--count must be at least 1"));
+    }
+    let mut policy = Policy::default();
+    let mut vault = None;
+    if args.uses_vault() {
+        let db = db.ok_or_else(|| anyhow::anyhow!("Profiles need a vault"))?;
+        require_vault(db)?;
+        let master = prompt_master_key(db, "Master password: ", global)?;
+        let Some(vault_key) = encrypt_decrypt::unlock(db, &master)? else {
+            return Err(anyhow::anyhow!("Wrong Password"));
+        };
+        let profiles = generator::load_profiles(db, &vault_key)?;
+        if let Some(name) = &args.profile {
+            policy = profiles.get(name).cloned().ok_or_else(|| anyhow::anyhow!("No profile named {}", name))?;
+        } else if let Some(site) = &args.site {
+            let (name, matched) = generator::profile_for(&profiles, site, std::slice::from_ref(site));
+            eprintln!("Using the {} profile.", name.unwrap_or("built-in"));
+            policy = matched;
+        }
+        vault = Some((db, vault_key));
+    }
+    args.apply(&mut policy);
+    policy.validate()?;
+
+    for _ in 0..args.count {
+        println!("{}", generator::generate(&policy)?.as_str());
+    }
+    if let (Some(name), Some((db, vault_key))) = (&args.save, &vault) {
+        generator::save_profile(db, vault_key, name, &policy)?;
+        eprintln!("Profile {} saved ({}).", name, policy.summary());
+    }
+    Ok(())
+}
+
+/// Prints passphrases and their entropy. No vault is needed.
+pub fn passphrase(args: &PassphraseArgs) -> Result<(), anyhow::Error> {
+    if args.count == 0 {
+        return Err(anyhow::anyhow!("--count must be at least 1"));
+    }
+    let policy = PassphrasePolicy {
+        words: args.words,
+        separator: args.separator.clone(),
+        capitalize: args.capitalize,
+        digits: args.digits,
+    };
+    policy.validate()?;
+    for _ in 0..args.count {
+        println!("{}", passphrase::generate(&policy)?.as_str());
+    }
+    eprintln!("{:.1} bits of entropy each.", policy.entropy());
+    Ok(())
+}
+
+fn breach_index_path(index: Option<PathBuf>, config: &Config) -> Result<PathBuf, anyhow::Error> {
+    index.or_else(|| config.breach_index()).ok_or_else(|| anyhow::anyhow!("No data directory found, pass --index"))
+}
+
+/// Builds the breach index. No vault is needed.
+pub fn build_breach_index(source: &Path, index: Option<PathBuf>, config: &Config) -> Result<(), anyhow::Error> {
+    let index = breach_index_path(index, config)?;
+    if let Some(parent) = index.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    eprintln!("Building {} from {}...", index.display(), source.display());
+    let (kind, records) = breach::build_index(source, &index)?;
+    eprintln!("Indexed {} {} hashes.", records, kind);
+    Ok(())
+}
+
+fn check_breaches(db: &Db, index: Option<PathBuf>, global: &GlobalArgs, config: &Config) -> Result<(), anyhow::Error> {
+    require_vault(db)?;
+    let path = breach_index_path(index, config)?;
+    if !path.exists() {
+        return Err(anyhow::anyhow!("No breach index at {}, build one with `rustpass breach index`", path.display()));
+    }
+    let mut index = breach::BreachIndex::open(&path)?;
+    let master = prompt_master_key(db, "Master password: ", global)?;
+    let Some(vault_key) = encrypt_decrypt::unlock(db, &master)? else {
+        return Err(anyhow::anyhow!("Wrong Password"));
+    };
+    let entries = entry::list_entries(db, &vault_key)?;
+    let breached = breach::breached_entries(&mut index, &entries)?;
+    for (id, entry) in &entries {
+        match entry {
+            Ok(entry) => {
+                if let Some(count) = breached.get(id) {
+                    println!("{}  seen {} times", entry.title, count);
+                }
+            }
+            Err(e) => eprintln!("Skipped {}: {}", id, e),
+        }
+    }
+    eprintln!("{} of {} entries have a breached password ({} index, {} hashes).", breached.len(), entries.len(), index.kind(), index.records());
+    Ok(())
+}
+
+fn health(db: &Db, json: bool, max_age_days: u64, global: &GlobalArgs, config: &Config) -> Result<(), anyhow::Error> {
+    require_vault(db)?;
+    let master = prompt_master_key(db, "Master password: ", global)?;
+    let Some(vault_key) = encrypt_decrypt::unlock(db, &master)? else {
+        return Err(anyhow::anyhow!("Wrong Password"));
+    };
+    let entries = entry::list_entries(db, &vault_key)?;
+    let breached = breach::find_breaches(config.breach_index().as_deref(), &entries)?;
+    let report = health::report(&entries, &breached, max_age_days);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+
+    let summary = &report.summary;
+    println!("Score: {}% of {} entries have no issues", summary.score, summary.entries - summary.unreadable);
+    println!(
+        "Breached: {}  Reused: {}  Weak: {}  Older than {} days: {}  Empty: {}  Unreadable: {}",
+        summary.breached, summary.reused, summary.weak, max_age_days, summary.old, summary.empty, summary.unreadable
+    );
+    for entry in &report.entries {
+        let issues: Vec<String> = entry.issues.iter().map(|issue| issue.describe()).collect();
+        println!("{}: {}", entry.title, issues.join("; "));
+    }
+    for id in &report.unreadable {
+        println!("{}: cannot be decrypted", id);
+    }
+    Ok(())
+}
+
+fn profile(db: &Db, action: ProfileAction, global: &GlobalArgs) -> Result<(), anyhow::Error> {
+    require_vault(db)?;
+    let master = prompt_master_key(db, "Master password: ", global)?;
+    let Some(vault_key) = encrypt_decrypt::unlock(db, &master)? else {
+        return Err(anyhow::anyhow!("Wrong Password"));
+    };
+    match action {
+        ProfileAction::List => {
+            let profiles = generator::load_profiles(db, &vault_key)?;
+            if profiles.is_empty() {
+                eprintln!("No saved profiles.");
+            }
+            for (name, policy) in &profiles {
+                println!("{}  {}", name, policy.summary());
+            }
+        }
+        ProfileAction::Delete { profile: name } => {
+            if !generator::delete_profile(db, &vault_key, &name)? {
+                return Err(anyhow::anyhow!("No profile named {}", name));
+            }
+            eprintln!("Profile {} deleted.", name);
+        }
+    }
+    Ok(())
+}
+
+/// Reads a password without echoing it. When stdin is not a terminal the
+/// next line is read instead, so scripts can pipe passwords in.
+pub fn prompt_password(prompt: &str) -> Result<SecretString, anyhow::Error> {
+    if !stdin().is_terminal() {
+        let mut line = Zeroizing::new(String::new());
+        stdin().lock().read_line(&mut line)?;
+        return Ok(SecretString::from(line.trim_end_matches(['\r', '\n'])));
+    }
+
+    eprint!("{}", prompt);
+    stderr().flush()?;
+    enable_raw_mode()?;
+    let result = read_hidden_line();
+    disable_raw_mode()?;
+    eprintln!();
+    result
+}
+
+fn read_hidden_line() -> Result<SecretString, anyhow::Error> {
+    let mut password = SecretString::new();
+    loop {
+        if let Event::Key(key) = event::read()? {
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                KeyCode::Enter => return Ok(password),
+                KeyCode::Esc => return Err(anyhow::anyhow!("Cancelled")),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    return Err(anyhow::anyhow!("Cancelled"));
+                }
+                KeyCode::Backspace => { password.pop(); },
+                KeyCode::Char(c) => password.push(c),
+                _ => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,176 @@
+//! Where the vault lives. An explicit path comes from, in order: the
+//! `--vault` flag, the `RUSTPASS_VAULT` environment variable and `vault` in
+//! the config file. Without one, a named vault is used: the one given with
+//! `--name`, picked in the TUI, or the last one used.
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::breach;
+use crate::health;
+use crate::strength;
+
+/// Environment variable overriding the vault path.
+pub const VAULT_ENV: &str = "RUSTPASS_VAULT";
+
+const DEFAULT_MIN_MASTER_STRENGTH: u8 = 2;
+
+const DEFAULT_REVEAL_SECONDS: u64 = 15;
+
+/// Vault directory used by versions that opened `rustpass_db` in the current
+/// directory.
+const LEGACY_VAULT: &str = "rustpass_db";
+
+/// Contents of `rustpass/config.toml` under the XDG config directory.
+#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Config {
+    /// Vault database to open when neither `--vault` nor `RUSTPASS_VAULT` is set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vault: Option<PathBuf>,
+    /// Named vault opened last, used when no vault is given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_vault: Option<String>,
+    /// Lowest strength score, 0 to 4, accepted for a new master password.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_master_strength: Option<u8>,
+    /// Breach index to check passwords against, instead of the default one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub breach_index: Option<PathBuf>,
+    /// Passwords older than this many days are reported by the health report.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_password_age_days: Option<u64>,
+    /// How long a revealed password stays on screen, in seconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reveal_seconds: Option<u64>,
+    /// Never show passwords and hidden values on screen, only copy them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub copy_only: Option<bool>,
+}
+
+impl Config {
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("rustpass").join("config.toml"))
+    }
+
+    /// Reads the config file, or returns the default config if there is none.
+    pub fn load() -> Result<Self, anyhow::Error> {
+        let Some(path) = Self::path() else {
+            return Ok(Config::default());
+        };
+        match std::fs::read_to_string(&path) {
+            Ok(text) => toml::from_str(&text)
+                .map_err(|e| anyhow::anyhow!("Invalid config file {}: {}", path.display(), e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self) -> Result<(), anyhow::Error> {
+        let path = Self::path().ok_or_else(|| anyhow::anyhow!("No config directory found"))?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Lowest strength score accepted for a new master password, "fair" unless
+    /// configured otherwise.
+    pub fn min_master_strength(&self) -> u8 {
+        self.min_master_strength.unwrap_or(DEFAULT_MIN_MASTER_STRENGTH).min(strength::MAX_SCORE)
+    }
+
+    /// Path of the breach index, whether or not it has been built yet.
+    pub fn breach_index(&self) -> Option<PathBuf> {
+        self.breach_index.clone().or_else(breach::default_index_path)
+    }
+
+    /// Age in days after which the health report flags a password, a year
+    /// unless configured otherwise.
+    pub fn max_password_age_days(&self) -> u64 {
+        self.max_password_age_days.unwrap_or(health::DEFAULT_MAX_AGE_DAYS)
+    }
+
+    /// How long a revealed password stays on screen before it is masked
+    /// again, 15 seconds unless configured otherwise.
+    pub fn reveal_timeout(&self) -> Duration {
+        Duration::from_secs(self.reveal_seconds.unwrap_or(DEFAULT_REVEAL_SECONDS))
+    }
+
+    pub fn copy_only(&self) -> bool {
+        self.copy_only.unwrap_or(false)
+    }
+
+    /// Records `name` as the last used vault.
+    pub fn remember_vault(&mut self, name: &str) -> Result<(), anyhow::Error> {
+        if self.last_vault.as_deref() != Some(name) {
+            self.last_vault = Some(name.to_string());
+            self.save()?;
+        }
+        Ok(())
+    }
+}
+
+fn resolve(flag: Option<&Path>, env: Option<OsString>, config: &Config) -> Option<PathBuf> {
+    flag.map(Path::to_path_buf)
+        .or_else(|| env.filter(|value| !value.is_empty()).map(PathBuf::from))
+        .or_else(|| config.vault.clone())
+}
+
+/// Returns the vault path given by the flag, the environment or the config
+/// file, in that order, or `None` if a named vault should be used instead.
+pub fn explicit_vault_path(flag: Option<&Path>, config: &Config) -> Option<PathBuf> {
+    resolve(flag, std::env::var_os(VAULT_ENV), config)
+}
+
+/// Older versions kept the vault in `rustpass_db` in the current directory.
+/// Refuses to silently create an empty vault at `path` when such a vault
+/// exists and `path` does not.
+pub fn check_legacy_vault(path: &Path) -> Result<(), anyhow::Error> {
+    if !path.exists() && Path::new(LEGACY_VAULT).is_dir() {
+        return Err(anyhow::anyhow!(
+            "Found a vault in ./{} from an older version. Pass --vault {} to use it, or move it to {}",
+            LEGACY_VAULT, LEGACY_VAULT, path.display()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vault_path_precedence() {
+        let config = Config { vault: Some(PathBuf::from("/config/vault")), ..Config::default() };
+        let env = || Some(OsString::from("/env/vault"));
+
+        assert_eq!(resolve(Some(Path::new("/flag/vault")), env(), &config), Some(PathBuf::from("/flag/vault")));
+        assert_eq!(resolve(None, env(), &config), Some(PathBuf::from("/env/vault")));
+        assert_eq!(resolve(None, Some(OsString::new()), &config), Some(PathBuf::from("/config/vault")));
+        assert_eq!(resolve(None, None, &Config::default()), None);
+    }
+
+    #[test]
+    fn test_config_file() {
+        let config: Config = toml::from_str("vault = \"/home/me/vault\"\nlast_vault = \"staging\"").expect("valid config");
+        assert_eq!(config.vault, Some(PathBuf::from("/home/me/vault")));
+        assert_eq!(config.last_vault.as_deref(), Some("staging"));
+        assert_eq!(toml::from_str::<Config>("").expect("empty config"), Config::default());
+
+        let written = toml::to_string(&Config { last_vault: Some("personal".to_string()), ..Config::default() }).expect("serializes");
+        assert_eq!(written.trim(), "last_vault = \"personal\"");
+
+        assert_eq!(Config::default().min_master_strength(), 2);
+        let strict: Config = toml::from_str("min_master_strength = 9").expect("valid config");
+        assert_eq!(strict.min_master_strength(), strength::MAX_SCORE);
+
+        assert_eq!(Config::default().reveal_timeout(), Duration::from_secs(15));
+        assert!(!Config::default().copy_only());
+        let private: Config = toml::from_str("reveal_seconds = 5\ncopy_only = true").expect("valid config");
+        assert_eq!((private.reveal_timeout(), private.copy_only()), (Duration::from_secs(5), true));
+    }
+}
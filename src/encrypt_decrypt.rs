@@ -1,102 +1,750 @@
-use aes_gcm::{aead::{consts::{U12}, generic_array::GenericArray, Aead}, Aes256Gcm, Key, KeyInit, Nonce};
-use anyhow::Ok;
-use argon2::{Argon2};
+use aes_gcm::{aead::{consts::{U12}, generic_array::GenericArray, Aead, Payload}, Aes256Gcm, Key, KeyInit, Nonce};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use argon2::{password_hash::{self, PasswordHash, PasswordHasher, PasswordVerifier, SaltString}, Algorithm, Argon2, Params, Version};
 use rand::{rngs::OsRng, TryRngCore};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use subtle::ConstantTimeEq;
+use zeroize::Zeroizing;
+use sled::Db;
+use crate::entry;
+use crate::generator;
+use crate::error::Error;
+use crate::secret::SecretBytes;
 use crate::sleddb;
 
+const SALT_LEN: usize = 16;
+/// Nonce length of AES-256-GCM, and of every blob older than the cipher id.
+const NONCE_LEN: usize = 12;
+const XNONCE_LEN: usize = 24;
+const KEY_LEN: usize = 32;
 
+/// Prefix of versioned blobs. Legacy blobs start with a random salt instead,
+/// so they never carry this prefix in practice.
+const MAGIC: &[u8; 4] = b"RPSS";
+/// Version 1 blobs are `[magic][version][nonce][ciphertext]` under the vault key.
+const FORMAT_V1: u8 = 1;
+/// Version 2 blobs carry the full header described on [`Header`].
+const FORMAT_V2: u8 = 2;
+/// Version 3 blobs also authenticate caller-supplied context (the vault ID
+/// and entry key for entries) as associated data.
+pub const FORMAT_VERSION: u8 = 3;
 
-pub fn encrypt(plaintext_pass: &[u8],masterpass: &[u8]) -> Result<Vec<u8>, anyhow::Error>{
-    let mut salt= [0u8; 16];
-    OsRng.try_fill_bytes(&mut salt)?;
+/// Length of the random vault ID bound into every entry blob.
+pub const VAULT_ID_LEN: usize = 16;
 
-    let mut key_str = [0u8;32];
-    let argon2 = Argon2::default();
-    argon2.hash_password_into(&masterpass, &salt, &mut key_str).expect("Unable to hash key");
+const CIPHER_AES256GCM: u8 = 1;
+const CIPHER_XCHACHA20POLY1305: u8 = 2;
+
+const KDF_NONE: u8 = 0;
+const KDF_ARGON2ID: u8 = 1;
+
+/// Argon2id cost parameters, recorded in every password-encrypted blob.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KdfParams {
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        KdfParams {
+            m_cost: Params::DEFAULT_M_COST,
+            t_cost: Params::DEFAULT_T_COST,
+            p_cost: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+impl KdfParams {
+    /// Upper bound on the memory cost, in KiB (1 GiB). Also the most
+    /// [`calibrate`] picks.
+    const MAX_M_COST: u32 = 1024 * 1024;
+    const MAX_T_COST: u32 = 64;
+    const MAX_P_COST: u32 = 64;
+
+    /// Checks the parameters against Argon2's limits and our upper bounds, so
+    /// a damaged or hostile header cannot make unlocking exhaust memory or time.
+    pub fn new(m_cost: u32, t_cost: u32, p_cost: u32) -> Result<Self, Error> {
+        if m_cost > Self::MAX_M_COST || t_cost > Self::MAX_T_COST || p_cost > Self::MAX_P_COST {
+            return Err(Error::Kdf(format!(
+                "parameters exceed the limits (memory {} KiB, time {}, parallelism {})",
+                Self::MAX_M_COST, Self::MAX_T_COST, Self::MAX_P_COST
+            )));
+        }
+        let params = KdfParams { m_cost, t_cost, p_cost };
+        params.argon2()?;
+        Ok(params)
+    }
+
+    fn to_bytes(self) -> Vec<u8> {
+        [self.m_cost.to_le_bytes(), self.t_cost.to_le_bytes(), self.p_cost.to_le_bytes()].concat()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let mut reader = HeaderReader { blob: bytes, pos: 0 };
+        KdfParams::new(reader.u32()?, reader.u32()?, reader.u32()?)
+    }
+
+    fn argon2(&self) -> Result<Argon2<'static>, Error> {
+        let params = Params::new(self.m_cost, self.t_cost, self.p_cost, Some(KEY_LEN))
+            .map_err(|e| Error::Kdf(e.to_string()))?;
+        Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+    }
+
+    fn derive_key(&self, password: &[u8], salt: &[u8]) -> Result<Zeroizing<[u8; KEY_LEN]>, Error> {
+        let mut key = Zeroizing::new([0u8; KEY_LEN]);
+        self.argon2()?.hash_password_into(password, salt, key.as_mut()).map_err(|e| Error::Kdf(e.to_string()))?;
+        Ok(key)
+    }
+
+    /// Time taken by one key derivation with these parameters on this machine.
+    pub fn benchmark(&self) -> Result<Duration, Error> {
+        let start = Instant::now();
+        self.derive_key(b"rustpass calibration", &[0u8; SALT_LEN])?;
+        Ok(start.elapsed())
+    }
+}
+
+/// Suggests Argon2id parameters whose key derivation takes roughly `target`
+/// on this machine. Memory cost is doubled first, since it is what makes
+/// attacks expensive, and the time cost is then scaled to close the gap.
+/// Returns the parameters with their measured duration.
+pub fn calibrate(target: Duration, p_cost: u32) -> Result<(KdfParams, Duration), Error> {
+    let mut params = KdfParams::new(KdfParams::default().m_cost, 1, p_cost)?;
+    let mut elapsed = params.benchmark()?;
+
+    while elapsed * 2 <= target && params.m_cost * 2 <= KdfParams::MAX_M_COST {
+        params.m_cost *= 2;
+        elapsed = params.benchmark()?;
+    }
+
+    let scale = target.as_secs_f64() / elapsed.as_secs_f64().max(f64::EPSILON);
+    let t_cost = (scale.round() as u32).clamp(1, KdfParams::MAX_T_COST);
+    if t_cost != params.t_cost {
+        params.t_cost = t_cost;
+        elapsed = params.benchmark()?;
+    }
+    Ok((params, elapsed))
+}
+
+/// Argon2 parameters configured for the vault. Vaults created before they
+/// were configurable use the Argon2 defaults.
+pub fn vault_kdf_params(db: &Db) -> Result<KdfParams, Error> {
+    match sleddb::get(db, sleddb::KDF_PARAMS)? {
+        Some(bytes) => KdfParams::from_bytes(&bytes),
+        None => Ok(KdfParams::default()),
+    }
+}
+
+/// Key derivation recorded in a blob header.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Kdf {
+    /// Encrypted directly with the vault key.
+    None,
+    Argon2id { params: KdfParams, salt: Vec<u8> },
+}
+
+/// AEAD cipher used for a blob, recorded by id in its header.
+///
+/// AES-256-GCM is fast with AES-NI but its 96-bit random nonces limit how many
+/// blobs one key can safely encrypt. XChaCha20-Poly1305 has 192-bit nonces
+/// and is fast in software.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Cipher {
+    #[default]
+    Aes256Gcm,
+    XChaCha20Poly1305,
+}
+
+impl Cipher {
+    pub const ALL: [Cipher; 2] = [Cipher::Aes256Gcm, Cipher::XChaCha20Poly1305];
+
+    pub fn id(self) -> u8 {
+        match self {
+            Cipher::Aes256Gcm => CIPHER_AES256GCM,
+            Cipher::XChaCha20Poly1305 => CIPHER_XCHACHA20POLY1305,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Result<Self, Error> {
+        match id {
+            CIPHER_AES256GCM => Ok(Cipher::Aes256Gcm),
+            CIPHER_XCHACHA20POLY1305 => Ok(Cipher::XChaCha20Poly1305),
+            id => Err(Error::UnknownFormat(format!("cipher id {}", id))),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Cipher::Aes256Gcm => "aes256gcm",
+            Cipher::XChaCha20Poly1305 => "xchacha20poly1305",
+        }
+    }
+
+    fn nonce_len(self) -> usize {
+        match self {
+            Cipher::Aes256Gcm => NONCE_LEN,
+            Cipher::XChaCha20Poly1305 => XNONCE_LEN,
+        }
+    }
+
+    fn encrypt(self, key: &[u8], nonce: &[u8], payload: Payload) -> Result<Vec<u8>, Error> {
+        match self {
+            Cipher::Aes256Gcm => {
+                let nonce: &GenericArray<u8, U12> = Nonce::from_slice(nonce);
+                Aes256Gcm::new_from_slice(key).map_err(|_| Error::Encryption)?.encrypt(nonce, payload)
+            }
+            Cipher::XChaCha20Poly1305 => {
+                XChaCha20Poly1305::new_from_slice(key).map_err(|_| Error::Encryption)?.encrypt(XNonce::from_slice(nonce), payload)
+            }
+        }
+        .map_err(|_| Error::Encryption)
+    }
+
+    fn decrypt(self, key: &[u8], nonce: &[u8], payload: Payload) -> Result<Vec<u8>, Error> {
+        match self {
+            Cipher::Aes256Gcm => {
+                let nonce: &GenericArray<u8, U12> = Nonce::from_slice(nonce);
+                Aes256Gcm::new_from_slice(key).map_err(|_| Error::Encryption)?.decrypt(nonce, payload)
+            }
+            Cipher::XChaCha20Poly1305 => {
+                XChaCha20Poly1305::new_from_slice(key).map_err(|_| Error::Encryption)?.decrypt(XNonce::from_slice(nonce), payload)
+            }
+        }
+        .map_err(|_| Error::Authentication)
+    }
+}
+
+impl fmt::Display for Cipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Cipher {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Cipher::ALL
+            .into_iter()
+            .find(|cipher| cipher.name().eq_ignore_ascii_case(name))
+            .ok_or_else(|| Error::InvalidInput(format!("unknown cipher {}, expected aes256gcm or xchacha20poly1305", name)))
+    }
+}
+
+/// Returns the cipher new blobs in this vault are encrypted with.
+pub fn vault_cipher(db: &Db) -> Result<Cipher, Error> {
+    match sleddb::get(db, sleddb::CIPHER)? {
+        Some(id) if id.len() == 1 => Cipher::from_id(id[0]),
+        Some(_) => Err(Error::Corrupted("invalid cipher record".to_string())),
+        None => Ok(Cipher::default()),
+    }
+}
+
+/// Header of a versioned blob:
+/// `[magic (4)][version (1)][cipher id (1)][kdf id (1)][kdf params]`, where the
+/// Argon2id params are `[m_cost u32][t_cost u32][p_cost u32][salt len (1)][salt]`
+/// (little-endian). The nonce and ciphertext follow. From version 2 the header
+/// bytes are authenticated as associated data, and from version 3 so is the
+/// context passed by the caller.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub version: u8,
+    pub cipher: Cipher,
+    pub kdf: Kdf,
+}
+
+impl Header {
+    fn new(cipher: Cipher, kdf: Kdf) -> Self {
+        Header { version: FORMAT_VERSION, cipher, kdf }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(MAGIC);
+        out.push(self.version);
+        out.push(self.cipher.id());
+        match &self.kdf {
+            Kdf::None => out.push(KDF_NONE),
+            Kdf::Argon2id { params, salt } => {
+                out.push(KDF_ARGON2ID);
+                out.extend_from_slice(&params.m_cost.to_le_bytes());
+                out.extend_from_slice(&params.t_cost.to_le_bytes());
+                out.extend_from_slice(&params.p_cost.to_le_bytes());
+                out.push(salt.len() as u8);
+                out.extend_from_slice(salt);
+            }
+        }
+        out
+    }
+
+    /// Parses the header at the start of a blob, returning it with its length
+    /// in bytes, or `None` for a legacy headerless blob.
+    pub fn parse(blob: &[u8]) -> Result<Option<(Header, usize)>, Error> {
+        if blob.len() <= MAGIC.len() || !blob.starts_with(MAGIC) {
+            return Ok(None);
+        }
+        let version = blob[MAGIC.len()];
+        if version == FORMAT_V1 {
+            let header = Header { version, cipher: Cipher::Aes256Gcm, kdf: Kdf::None };
+            return Ok(Some((header, MAGIC.len() + 1)));
+        }
+        if version != FORMAT_V2 && version != FORMAT_VERSION {
+            return Err(Error::UnknownFormat(format!("version {}", version)));
+        }
+
+        let mut reader = HeaderReader { blob, pos: MAGIC.len() + 1 };
+        let cipher = Cipher::from_id(reader.byte()?)?;
+        let kdf = match reader.byte()? {
+            KDF_NONE => Kdf::None,
+            KDF_ARGON2ID => {
+                let params = KdfParams::new(reader.u32()?, reader.u32()?, reader.u32()?)?;
+                let salt_len = reader.byte()? as usize;
+                let salt = reader.take(salt_len)?.to_vec();
+                Kdf::Argon2id { params, salt }
+            }
+            id => return Err(Error::UnknownFormat(format!("KDF id {}", id))),
+        };
+        Ok(Some((Header { version, cipher, kdf }, reader.pos)))
+    }
+}
 
-    let mut nonce_byte = [0u8;12];
+struct HeaderReader<'a> {
+    blob: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> HeaderReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        let bytes = self.blob.get(self.pos..self.pos + len).ok_or(Error::Truncated)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        let bytes: [u8; 4] = self.take(4)?.try_into().map_err(|_| Error::Truncated)?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+}
+
+/// Random data-encryption key for a vault. It is unwrapped once at login with
+/// the master password and then used directly for every entry, with the
+/// vault's cipher. The key is wiped from memory when dropped, which happens
+/// when the vault is locked.
+pub struct VaultKey {
+    key: SecretBytes,
+    cipher: Cipher,
+}
+
+impl VaultKey {
+    pub fn generate(cipher: Cipher) -> Result<Self, Error> {
+        let mut key = SecretBytes::zeroed(KEY_LEN);
+        OsRng.try_fill_bytes(key.as_mut_slice())?;
+        Ok(VaultKey { key, cipher })
+    }
+
+    pub(crate) fn from_slice(bytes: &[u8], cipher: Cipher) -> Result<Self, Error> {
+        if bytes.len() != KEY_LEN {
+            return Err(Error::Corrupted("invalid vault key length".to_string()));
+        }
+        Ok(VaultKey { key: SecretBytes::new(bytes.to_vec()), cipher })
+    }
+
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        &self.key
+    }
+
+    pub fn cipher(&self) -> Cipher {
+        self.cipher
+    }
+}
+
+/// The secret that unlocks a vault: the master password on its own, or, for
+/// vaults that use a key file, `SHA-256(password) || SHA-256(key file)` so
+/// neither factor alone is enough.
+pub struct MasterKey {
+    bytes: SecretBytes,
+    key_file: bool,
+}
+
+impl MasterKey {
+    pub fn new(password: &[u8], key_file: Option<&Path>) -> Result<Self, Error> {
+        let Some(path) = key_file else {
+            return Ok(MasterKey { bytes: SecretBytes::new(password.to_vec()), key_file: false });
+        };
+        let contents = SecretBytes::new(std::fs::read(path)?);
+        if contents.is_empty() {
+            return Err(Error::Corrupted(format!("key file {} is empty", path.display())));
+        }
+        let mut bytes = SecretBytes::zeroed(2 * KEY_LEN);
+        bytes.as_mut_slice()[..KEY_LEN].copy_from_slice(&Sha256::digest(password));
+        bytes.as_mut_slice()[KEY_LEN..].copy_from_slice(&Sha256::digest(&contents[..]));
+        Ok(MasterKey { bytes, key_file: true })
+    }
+
+    pub fn uses_key_file(&self) -> bool {
+        self.key_file
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+/// Returns true if the blob is written in the current format version.
+pub fn is_current_format(encrypted_text: &[u8]) -> bool {
+    matches!(Header::parse(encrypted_text), Ok(Some((header, _))) if header.version == FORMAT_VERSION)
+}
+
+/// Returns true if the blob is in the current format and encrypted with `cipher`.
+pub fn is_current_with(encrypted_text: &[u8], cipher: Cipher) -> bool {
+    matches!(Header::parse(encrypted_text), Ok(Some((header, _))) if header.version == FORMAT_VERSION && header.cipher == cipher)
+}
+
+/// Creates a random vault ID.
+pub fn generate_vault_id() -> Result<Vec<u8>, Error> {
+    let mut vault_id = vec![0u8; VAULT_ID_LEN];
+    OsRng.try_fill_bytes(&mut vault_id)?;
+    Ok(vault_id)
+}
+
+/// Associated data binding an entry blob to its vault and entry key, so a
+/// blob swapped to another key or copied from another vault fails to decrypt.
+pub fn entry_context(vault_id: &[u8], key: &str) -> Vec<u8> {
+    [vault_id, key.as_bytes()].concat()
+}
+
+/// Encrypts under `header`, producing `[header][nonce][ciphertext]`.
+fn seal(key: &[u8], header: &Header, plaintext: &[u8], context: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut nonce_byte = vec![0u8; header.cipher.nonce_len()];
     OsRng.try_fill_bytes(&mut nonce_byte)?;
-    let nonce:&GenericArray<u8,U12> = Nonce::from_slice(&nonce_byte);
 
-    
-    let key = Key::<Aes256Gcm>::from_slice(&key_str);
-    let cipher = Aes256Gcm::new(&key);
-    let cipher_text = cipher.encrypt(nonce, plaintext_pass).expect("Unable to encrypt");
+    let mut output = header.encode();
+    let aad = [&output[..], context].concat();
+    let cipher_text = header.cipher.encrypt(key, &nonce_byte, Payload { msg: plaintext, aad: &aad })?;
 
-    let mut output = Vec::new();
-    output.extend_from_slice(&salt);
     output.extend_from_slice(&nonce_byte);
     output.extend_from_slice(&cipher_text);
+    Ok(output)
+}
 
+/// Decrypts the body following a parsed header of `header_len` bytes.
+fn open(key: &[u8], header: &Header, header_len: usize, encrypted_text: &[u8], context: &[u8]) -> Result<SecretBytes, Error> {
+    let (header_bytes, rest) = encrypted_text.split_at(header_len);
+    if rest.len() < header.cipher.nonce_len() {
+        return Err(Error::Truncated);
+    }
+    let (nonce_bytes, ciphertext) = rest.split_at(header.cipher.nonce_len());
+    let aad = match header.version {
+        FORMAT_V1 => Vec::new(),
+        FORMAT_V2 => header_bytes.to_vec(),
+        _ => [header_bytes, context].concat(),
+    };
 
-    Ok(output)
+    let plaintext = header.cipher.decrypt(key, nonce_bytes, Payload { msg: ciphertext, aad: &aad })?;
+
+    Ok(SecretBytes::new(plaintext))
+}
+
+/// Encrypts with the vault key and its cipher, authenticating `context`
+/// alongside the header.
+pub fn encrypt_with_key(plaintext: &[u8], key: &VaultKey, context: &[u8]) -> Result<Vec<u8>, Error> {
+    seal(&key.key, &Header::new(key.cipher, Kdf::None), plaintext, context)
+}
+
+/// Decrypts a blob encrypted with the vault key in any format version.
+/// Versions before 3 carry no context, so `context` is only checked for
+/// current blobs; use [`decrypt_entry`] where older blobs must be refused.
+pub fn decrypt_with_key(encrypted_text: &[u8], key: &VaultKey, context: &[u8]) -> Result<SecretBytes, Error> {
+    match Header::parse(encrypted_text)? {
+        Some((header, header_len)) if header.kdf == Kdf::None => open(&key.key, &header, header_len, encrypted_text, context),
+        _ => Err(Error::UnknownFormat("not encrypted with the vault key".to_string())),
+    }
 }
 
-pub fn decrypt(encrypted_text:&[u8], masterpass: &[u8]) -> Result<Vec<u8>, anyhow::Error>{
-    if encrypted_text.len() < 16 + 12{
-        return Err(anyhow::anyhow!("Encrypted blob to short"));
+/// Decrypts an entry blob, refusing formats that predate context binding so
+/// an unbound blob cannot be transplanted into a migrated vault.
+pub fn decrypt_entry(encrypted_text: &[u8], key: &VaultKey, context: &[u8]) -> Result<SecretBytes, Error> {
+    if !is_current_format(encrypted_text) {
+        return Err(Error::UnknownFormat("entry is not bound to this vault, run `rustpass upgrade`".to_string()));
+    }
+    decrypt_with_key(encrypted_text, key, context)
+}
+
+/// Password-based encryption with Argon2id, recording the cipher, KDF
+/// parameters and salt in the header. Used to wrap the vault key under the
+/// master password.
+pub fn encrypt(plaintext_pass: &[u8],masterpass: &[u8], params: &KdfParams, cipher: Cipher) -> Result<Vec<u8>, Error>{
+    let mut salt= [0u8; SALT_LEN];
+    OsRng.try_fill_bytes(&mut salt)?;
+
+    let key = params.derive_key(masterpass, &salt)?;
+    seal(key.as_ref(), &Header::new(cipher, Kdf::Argon2id { params: *params, salt: salt.to_vec() }), plaintext_pass, &[])
+}
+
+/// Password-based decryption. Reads both versioned blobs and the legacy
+/// headerless `[salt][nonce][ciphertext]` layout, which used default Argon2
+/// parameters.
+pub fn decrypt(encrypted_text:&[u8], masterpass: &[u8]) -> Result<SecretBytes, Error>{
+    if let Some((header, header_len)) = Header::parse(encrypted_text)? {
+        return match &header.kdf {
+            Kdf::Argon2id { params, salt } => {
+                let key = params.derive_key(masterpass, salt)?;
+                open(key.as_ref(), &header, header_len, encrypted_text, &[])
+            }
+            Kdf::None => Err(Error::UnknownFormat("not password encrypted".to_string())),
+        };
+    }
+
+    if encrypted_text.len() < SALT_LEN + NONCE_LEN{
+        return Err(Error::Truncated);
     }   
-    let (salt_bytes, rest) = encrypted_text.split_at(16);
-    let (nonce_bytes, ciphertext) = rest.split_at(12);
+    let (salt_bytes, rest) = encrypted_text.split_at(SALT_LEN);
+    let (nonce_bytes, ciphertext) = rest.split_at(NONCE_LEN);
 
-    let mut key = [0u8;32];
+    let mut key = Zeroizing::new([0u8;KEY_LEN]);
     let argon2 = Argon2::default();
-    argon2.hash_password_into(&masterpass, &salt_bytes, &mut key).expect("Unable to create key");
+    argon2.hash_password_into(masterpass, salt_bytes, key.as_mut()).map_err(|e| Error::Kdf(e.to_string()))?;
 
-    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key.as_ref()));
     let nonce: &GenericArray<u8,U12> = Nonce::from_slice(nonce_bytes);
 
-    let plaintext = cipher.decrypt(nonce, ciphertext).expect("Unable to Decrypt");
+    let plaintext = cipher.decrypt(nonce, ciphertext).map_err(|_| Error::Authentication)?;
 
-    Ok(plaintext)
+    Ok(SecretBytes::new(plaintext))
 }
 
-pub fn store_master_password(password: &[u8]) -> Result<(), anyhow::Error> {
-    let mut salt_bytes = [0u8; 16];
+/// Hashes the master password into a PHC-format Argon2id string. It uses its
+/// own random salt, so it shares nothing with the key that wraps the vault key.
+fn master_password_verifier(password: &[u8], params: &KdfParams) -> Result<String, Error> {
+    let mut salt_bytes = [0u8; SALT_LEN];
     OsRng.try_fill_bytes(&mut salt_bytes)?;
-    let mut key = [0u8; 32];
-    let argon2 = Argon2::default();
-    argon2.hash_password_into(password, &salt_bytes, &mut key).expect("Unable to hash password");
-    sleddb::insert("salt", &salt_bytes.to_vec())?;
-    sleddb::insert("hash", &key.to_vec())?;
-    Ok(())
+    let salt = SaltString::encode_b64(&salt_bytes).map_err(|e| Error::Kdf(e.to_string()))?;
+    let hash = params.argon2()?.hash_password(password, &salt).map_err(|e| Error::Kdf(e.to_string()))?;
+    Ok(hash.to_string())
+}
+
+/// Builds the verifier, KDF parameter, key file flag, cipher and wrapped vault
+/// key records for a master key.
+fn master_password_records(master: &MasterKey, vault_key: &VaultKey, params: &KdfParams) -> Result<Vec<(&'static str, Vec<u8>)>, Error> {
+    let verifier = master_password_verifier(master.as_bytes(), params)?;
+
+    Ok(vec![
+        (sleddb::VERIFIER, verifier.into_bytes()),
+        (sleddb::KDF_PARAMS, params.to_bytes()),
+        (sleddb::KEY_FILE, vec![master.uses_key_file() as u8]),
+        (sleddb::CIPHER, vec![vault_key.cipher.id()]),
+        (sleddb::VAULT_KEY, wrap_vault_key(master, vault_key, params)?),
+    ])
+}
+
+/// Encrypts the vault key under the master key with the vault key's cipher.
+fn wrap_vault_key(master: &MasterKey, vault_key: &VaultKey, params: &KdfParams) -> Result<Vec<u8>, Error> {
+    encrypt(&vault_key.key, master.as_bytes(), params, vault_key.cipher)
+}
+
+/// Stores master password records, dropping the legacy `salt`/`hash` verifier
+/// in the same batch.
+fn write_master_password_records(db: &Db, records: &[(&str, Vec<u8>)]) -> Result<(), Error> {
+    sleddb::write_batch(db, records, &[sleddb::LEGACY_SALT, sleddb::LEGACY_HASH])
 }
 
-pub fn verify_master_password(password: &[u8]) -> Result<bool, anyhow::Error>{
-    let mut verified = false;
-    let salt = match sleddb::get("salt") {
+/// Sets up a new vault: stores the master password verifier, a fresh vault ID
+/// and a freshly generated vault key for `cipher` wrapped under the master
+/// password.
+pub fn store_master_password(db: &Db, master: &MasterKey, params: &KdfParams, cipher: Cipher) -> Result<VaultKey, Error> {
+    let vault_key = VaultKey::generate(cipher)?;
+    let mut records = master_password_records(master, &vault_key, params)?;
+    records.push((sleddb::VAULT_ID, generate_vault_id()?));
+    write_master_password_records(db, &records)?;
+    Ok(vault_key)
+}
+
+/// Opens an in-memory vault with the master password `Bloromo` and a cheap
+/// KDF, for tests.
+#[cfg(test)]
+pub fn temporary_vault() -> (Db, VaultKey) {
+    let db = sleddb::temporary();
+    let master = MasterKey::new(b"Bloromo", None).expect("unable to derive master key");
+    let params = KdfParams::new(8 * 1024, 1, 1).expect("valid KDF parameters");
+    let vault_key = store_master_password(&db, &master, &params, Cipher::default()).expect("unable to store master password");
+    (db, vault_key)
+}
+
+/// Replaces the master password, and adds or removes the key file if `new`
+/// differs from `old` in that respect. Only the verifier and the wrapped
+/// vault key change, and they are written in a single batch, so entries never
+/// need to be re-encrypted and a failure leaves the old password in place.
+/// Returns `false` if `old` is wrong.
+pub fn change_master_password(db: &Db, old: &MasterKey, new: &MasterKey) -> Result<bool, Error> {
+    let vault_key = match unlock(db, old)? {
+        Some(vault_key) => vault_key,
+        None => return Ok(false),
+    };
+    reset_master_password(db, &vault_key, new)?;
+    Ok(true)
+}
+
+/// Sets a new master password for a vault whose key was recovered some other
+/// way than with the old password, such as with the recovery key.
+pub(crate) fn reset_master_password(db: &Db, vault_key: &VaultKey, new: &MasterKey) -> Result<(), Error> {
+    write_master_password_records(db, &master_password_records(new, vault_key, &vault_kdf_params(db)?)?)
+}
+
+/// Switches the vault to new Argon2 parameters, recomputing the verifier and
+/// re-wrapping the vault key in one batch. Returns `false` if the password is
+/// wrong.
+pub fn set_kdf_params(db: &Db, master: &MasterKey, params: &KdfParams) -> Result<bool, Error> {
+    let vault_key = match unlock(db, master)? {
+        Some(vault_key) => vault_key,
+        None => return Ok(false),
+    };
+    write_master_password_records(db, &master_password_records(master, &vault_key, params)?)?;
+    Ok(true)
+}
+
+/// Checks the master password against the stored PHC verifier; the hash
+/// comparison inside `verify_password` is constant-time. The verifier's cost
+/// parameters are bounded like those in blob headers before Argon2 runs.
+/// Vaults still using
+/// the legacy raw `salt`/`hash` records are checked with a constant-time
+/// comparison and migrated to a PHC verifier on success.
+pub fn verify_master_password(db: &Db, master: &MasterKey) -> Result<bool, Error>{
+    let password = master.as_bytes();
+    if let Some(verifier) = sleddb::get(db, sleddb::VERIFIER)? {
+        let verifier = std::str::from_utf8(&verifier).map_err(|_| Error::Corrupted("verifier is not UTF-8".to_string()))?;
+        let hash = PasswordHash::new(verifier).map_err(|e| Error::Corrupted(format!("invalid verifier: {}", e)))?;
+        let cost = |name: &str| hash.params.get_decimal(name).ok_or_else(|| Error::Corrupted(format!("verifier has no {} parameter", name)));
+        KdfParams::new(cost("m")?, cost("t")?, cost("p")?).map_err(|e| Error::Corrupted(format!("invalid verifier: {}", e)))?;
+        return match Argon2::default().verify_password(password, &hash) {
+            Ok(()) => Ok(true),
+            Err(password_hash::Error::Password) => Ok(false),
+            Err(e) => Err(Error::Kdf(e.to_string())),
+        };
+    }
+
+    let salt = match sleddb::get(db, sleddb::LEGACY_SALT)? {
         Some(n) =>{
             n.to_vec()
         },
         None => {
-           return Err(anyhow::anyhow!("Missing Salt"));
+           return Err(Error::Corrupted("missing salt".to_string()));
         }
     };
 
-    let hash = match sleddb::get("hash") {
+    let hash = match sleddb::get(db, sleddb::LEGACY_HASH)? {
         Some(n) =>{
             n.to_vec()
         },
         None => {
-           return Err(anyhow::anyhow!("Missing hash"));
+           return Err(Error::Corrupted("missing hash".to_string()));
         }
     };
-    let mut derived_hash = [0u8; 32];
-    Argon2::default().hash_password_into(password, &salt, &mut derived_hash).expect("Unable to generate hash");
+    let params = vault_kdf_params(db)?;
+    let derived_hash = params.derive_key(password, &salt)?;
 
-    if hash == derived_hash.to_vec(){
-        verified = true;
+    let verified: bool = derived_hash.ct_eq(&hash[..]).into();
+    if verified {
+        let verifier = master_password_verifier(password, &params)?;
+        sleddb::write_batch(db, &[(sleddb::VERIFIER, verifier.into_bytes())], &[sleddb::LEGACY_SALT, sleddb::LEGACY_HASH])?;
     }
     
     Ok(verified)
 }
 
-pub fn is_master_password_configured() -> Result<bool, anyhow::Error> {
-    let has_salt = sleddb::get("salt").is_some(); 
-    let has_hash = sleddb::get("hash").is_some();
+pub fn is_master_password_configured(db: &Db) -> Result<bool, Error> {
+    if sleddb::get(db, sleddb::VERIFIER)?.is_some() {
+        return Ok(true);
+    }
+    let has_salt = sleddb::get(db, sleddb::LEGACY_SALT)?.is_some(); 
+    let has_hash = sleddb::get(db, sleddb::LEGACY_HASH)?.is_some();
     Ok(has_salt && has_hash)
 }
 
+/// Returns true if the vault was set up with a key file as second factor.
+pub fn requires_key_file(db: &Db) -> Result<bool, Error> {
+    Ok(sleddb::get(db, sleddb::KEY_FILE)?.is_some_and(|flag| flag.as_ref() == [1]))
+}
+
+/// Verifies the master password and returns the vault key for the session,
+/// or `None` if the password is wrong. Vaults created before the vault key
+/// or vault ID existed get them generated here. Entries still stored next to
+/// the metadata are then moved into the entries tree as records, and
+/// entries past the trash retention period are purged.
+pub fn unlock(db: &Db, master: &MasterKey) -> Result<Option<VaultKey>, Error> {
+    if !verify_master_password(db, master)? {
+        return Ok(None);
+    }
+    let password = master.as_bytes();
+
+    let mut metadata = Vec::new();
+    let vault_key = match sleddb::get(db, sleddb::VAULT_KEY)? {
+        Some(wrapped) => VaultKey::from_slice(&decrypt(&wrapped, password)?, vault_cipher(db)?)?,
+        None => {
+            let vault_key = VaultKey::generate(vault_cipher(db)?)?;
+            metadata.push((sleddb::VAULT_KEY, wrap_vault_key(master, &vault_key, &vault_kdf_params(db)?)?));
+            vault_key
+        }
+    };
+    if sleddb::get(db, sleddb::VAULT_ID)?.is_none() {
+        metadata.push((sleddb::VAULT_ID, generate_vault_id()?));
+    }
+
+    if !metadata.is_empty() {
+        sleddb::write_batch(db, &metadata, &[])?;
+    }
+    entry::migrate_legacy_entries(db, password, &vault_key)?;
+    entry::purge_expired_trash(db)?;
+    Ok(Some(vault_key))
+}
+
+/// Re-wraps the vault key with the vault's KDF parameters and rewrites every
+/// entry still in an older format. Returns the number of entries rewritten,
+/// or `None` if the password is wrong.
+pub fn upgrade_vault(db: &Db, master: &MasterKey) -> Result<Option<usize>, Error> {
+    let vault_key = match unlock(db, master)? {
+        Some(vault_key) => vault_key,
+        None => return Ok(None),
+    };
+    rewrite_vault(db, master, &vault_key).map(Some)
+}
+
+/// Switches the vault to `cipher`, re-encrypting the vault key and every
+/// entry in one batch. Blobs wrapped under the recovery key keep their
+/// cipher until the recovery key is regenerated, since it is not available
+/// here. Returns the number of entries rewritten, or `None` if the password
+/// is wrong.
+pub fn set_cipher(db: &Db, master: &MasterKey, cipher: Cipher) -> Result<Option<usize>, Error> {
+    let vault_key = match unlock(db, master)? {
+        Some(vault_key) => VaultKey { cipher, ..vault_key },
+        None => return Ok(None),
+    };
+    rewrite_vault(db, master, &vault_key).map(Some)
+}
+
+/// Re-wraps `vault_key` under the master key and rewrites every entry, and
+/// the generator profiles, not yet in the current format with the vault
+/// key's cipher.
+fn rewrite_vault(db: &Db, master: &MasterKey, vault_key: &VaultKey) -> Result<usize, Error> {
+    let mut metadata = vec![
+        (sleddb::VAULT_KEY, wrap_vault_key(master, vault_key, &vault_kdf_params(db)?)?),
+        (sleddb::CIPHER, vec![vault_key.cipher.id()]),
+    ];
+    if let Some(profiles) = generator::reseal_profiles(db, vault_key)? {
+        metadata.push((sleddb::GENERATOR_PROFILES, profiles));
+    }
+    sleddb::upgrade_entries(db, vault_key, &sleddb::vault_id(db)?, &metadata)
+}
+
 
 #[cfg(test)]
 mod tests {
@@ -106,10 +754,10 @@ mod tests {
     fn test_encrypt_decrypt() {
         let plaintext = b"hello";
         let masterpass = b"Shakalaka";
-        if let std::result::Result::Ok(encrypted) = encrypt(plaintext, masterpass){
+        if let Ok(encrypted) = encrypt(plaintext, masterpass, &KdfParams::default(), Cipher::default()){
             println!("Encrypted Bytes: {:?}", &encrypted);
 
-            if let std::result::Result::Ok(decrypted) = decrypt(encrypted.as_slice(), masterpass){
+            if let Ok(decrypted) = decrypt(encrypted.as_slice(), masterpass){
                 let decrypted_str = std::str::from_utf8(&decrypted).expect("Unable to convert to utf8");
                 println!("Decrypted message: {}",decrypted_str );       
                 assert_eq!(decrypted_str, std::str::from_utf8(plaintext).unwrap());
@@ -122,17 +770,295 @@ mod tests {
     
     }
 
+    /// Produces a blob in the headerless layout written by older versions.
+    fn legacy_encrypt(plaintext: &[u8], masterpass: &[u8]) -> Vec<u8> {
+        let salt = [7u8; SALT_LEN];
+        let nonce_byte = [9u8; NONCE_LEN];
+        let mut key = [0u8; KEY_LEN];
+        Argon2::default().hash_password_into(masterpass, &salt, &mut key).unwrap();
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+        let cipher_text = cipher.encrypt(Nonce::from_slice(&nonce_byte), plaintext).unwrap();
+        [&salt[..], &nonce_byte[..], &cipher_text[..]].concat()
+    }
+
+    #[test]
+    fn test_vault_key_round_trip() -> Result<(), anyhow::Error> {
+        let key = VaultKey::generate(Cipher::default())?;
+        let encrypted = encrypt_with_key(b"hello", &key, b"ctx")?;
+        assert!(is_current_format(&encrypted));
+        assert_eq!(&decrypt_with_key(&encrypted, &key, b"ctx")?[..], b"hello");
+
+        let password_blob = encrypt(b"hello", b"Shakalaka", &KdfParams::default(), Cipher::default())?;
+        assert!(decrypt_with_key(&password_blob, &key, b"ctx").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_ciphers() -> Result<(), anyhow::Error> {
+        for cipher in Cipher::ALL {
+            assert_eq!(cipher.name().parse::<Cipher>()?, cipher);
+            let key = VaultKey::generate(cipher)?;
+            let encrypted = encrypt_with_key(b"hello", &key, b"ctx")?;
+            assert_eq!(Header::parse(&encrypted)?.expect("header").0.cipher, cipher);
+            assert!(is_current_with(&encrypted, cipher));
+            assert_eq!(&decrypt_with_key(&encrypted, &key, b"ctx")?[..], b"hello");
+
+            // The header decides the cipher, whatever the key's own cipher is.
+            let other = Cipher::ALL.into_iter().find(|c| *c != cipher).expect("two ciphers");
+            let switched = VaultKey::from_slice(key.as_bytes(), other)?;
+            assert!(!is_current_with(&encrypted, other));
+            assert_eq!(&decrypt_with_key(&encrypted, &switched, b"ctx")?[..], b"hello");
+
+            let password_blob = encrypt(b"hello", b"Shakalaka", &KdfParams::new(8 * 1024, 1, 1)?, cipher)?;
+            assert_eq!(&decrypt(&password_blob, b"Shakalaka")?[..], b"hello");
+        }
+        assert!("rot13".parse::<Cipher>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_entry_context_binding() -> Result<(), anyhow::Error> {
+        let key = VaultKey::generate(Cipher::default())?;
+        let vault_id = generate_vault_id()?;
+        let bank = encrypt_with_key(b"hunter2", &key, &entry_context(&vault_id, "bank"))?;
+
+        assert_eq!(&decrypt_entry(&bank, &key, &entry_context(&vault_id, "bank"))?[..], b"hunter2");
+        assert!(matches!(decrypt_entry(&bank, &key, &entry_context(&vault_id, "forum")), Err(Error::Authentication)));
+        let other_vault = generate_vault_id()?;
+        assert!(matches!(decrypt_entry(&bank, &key, &entry_context(&other_vault, "bank")), Err(Error::Authentication)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_decrypt_errors() -> Result<(), anyhow::Error> {
+        let key = VaultKey::generate(Cipher::default())?;
+        let encrypted = encrypt_with_key(b"hello", &key, b"ctx")?;
+
+        let other_key = VaultKey::generate(Cipher::default())?;
+        assert!(matches!(decrypt_with_key(&encrypted, &other_key, b"ctx"), Err(Error::Authentication)));
+
+        let mut tampered = encrypted.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(matches!(decrypt_with_key(&tampered, &key, b"ctx"), Err(Error::Authentication)));
+
+        assert!(matches!(decrypt_with_key(&encrypted[..10], &key, b"ctx"), Err(Error::Truncated)));
+
+        let mut unknown = encrypted.clone();
+        unknown[MAGIC.len()] = 99;
+        assert!(matches!(decrypt_with_key(&unknown, &key, b"ctx"), Err(Error::UnknownFormat(_))));
+
+        assert!(matches!(decrypt(&encrypted[..20], b"Shakalaka"), Err(Error::UnknownFormat(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_header_round_trip() -> Result<(), anyhow::Error> {
+        let blob = encrypt(b"hello", b"Shakalaka", &KdfParams::default(), Cipher::default())?;
+        let (header, header_len) = Header::parse(&blob)?.expect("missing header");
+        assert_eq!(header.version, FORMAT_VERSION);
+        assert_eq!(header.cipher, Cipher::Aes256Gcm);
+        assert_eq!(header.kdf, Kdf::Argon2id { params: KdfParams::default(), salt: blob[header_len - SALT_LEN..header_len].to_vec() });
+        assert_eq!(header.encode(), &blob[..header_len]);
+
+        let mut hostile = blob.clone();
+        hostile[MAGIC.len() + 3..MAGIC.len() + 7].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(Header::parse(&hostile), Err(Error::Kdf(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_kdf_params_bytes() -> Result<(), anyhow::Error> {
+        let params = KdfParams::new(64 * 1024, 3, 2)?;
+        assert_eq!(KdfParams::from_bytes(&params.to_bytes())?, params);
+        assert!(KdfParams::new(1, 1, 1).is_err());
+        assert!(KdfParams::new(KdfParams::MAX_M_COST + 1, 1, 1).is_err());
+        assert!(KdfParams::new(8 * 1024, KdfParams::MAX_T_COST + 1, 1).is_err());
+        assert!(KdfParams::new(8 * 1024, 1, KdfParams::MAX_P_COST + 1).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_reads_older_formats() -> Result<(), anyhow::Error> {
+        let legacy = legacy_encrypt(b"hello", b"Shakalaka");
+        assert!(Header::parse(&legacy)?.is_none());
+        assert_eq!(&decrypt(&legacy, b"Shakalaka")?[..], b"hello");
+
+        let key = VaultKey::generate(Cipher::default())?;
+        let nonce_byte = [3u8; NONCE_LEN];
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key.key));
+        let cipher_text = cipher.encrypt(Nonce::from_slice(&nonce_byte), &b"hello"[..]).unwrap();
+        let v1 = [&MAGIC[..], &[FORMAT_V1], &nonce_byte[..], &cipher_text[..]].concat();
+        assert!(!is_current_format(&v1));
+        assert_eq!(&decrypt_with_key(&v1, &key, b"ctx")?[..], b"hello");
+        assert!(matches!(decrypt_entry(&v1, &key, b"ctx"), Err(Error::UnknownFormat(_))));
+
+        let header = Header { version: FORMAT_V2, cipher: Cipher::Aes256Gcm, kdf: Kdf::None }.encode();
+        let cipher_text = cipher.encrypt(Nonce::from_slice(&nonce_byte), Payload { msg: b"hello", aad: &header }).unwrap();
+        let v2 = [&header[..], &nonce_byte[..], &cipher_text[..]].concat();
+        assert!(!is_current_format(&v2));
+        assert_eq!(&decrypt_with_key(&v2, &key, b"ctx")?[..], b"hello");
+        assert!(matches!(decrypt_entry(&v2, &key, b"ctx"), Err(Error::UnknownFormat(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_unlock_baseline_vault() -> Result<(), anyhow::Error> {
+        let path = std::env::temp_dir().join(format!("rustpass-test-baseline-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        let password = b"Bloromo";
+        {
+            // The layout of the first versions: the raw verifier next to
+            // entries keyed by title, some titled like later metadata keys.
+            let db = sled::open(&path)?;
+            let salt = [5u8; SALT_LEN];
+            db.insert(sleddb::LEGACY_SALT, &salt[..])?;
+            db.insert(sleddb::LEGACY_HASH, &KdfParams::default().derive_key(password, &salt)?[..])?;
+            for title in [sleddb::CIPHER, "entry_format", sleddb::VERIFIER] {
+                db.insert(title, legacy_encrypt(title.as_bytes(), password))?;
+            }
+            db.flush()?;
+        }
+
+        let db = &sleddb::open(&path)?;
+        let vault_key = unlock(db, &MasterKey::new(password, None)?)?.expect("unlocks");
+        assert_eq!(vault_cipher(db)?, Cipher::default());
+        for title in [sleddb::CIPHER, "entry_format", sleddb::VERIFIER] {
+            let (_, found) = entry::find_by_title(db, &vault_key, title)?.expect("migrated");
+            assert_eq!(found.password.as_str(), title);
+        }
+        assert!(unlock(db, &MasterKey::new(password, None)?)?.is_some());
+        std::fs::remove_dir_all(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_cipher() -> Result<(), anyhow::Error> {
+        let db = &sleddb::temporary();
+        let bloromo = &MasterKey::new(b"Bloromo", None)?;
+        let vault_key = store_master_password(db, bloromo, &KdfParams::new(8 * 1024, 1, 1)?, Cipher::XChaCha20Poly1305)?;
+        assert_eq!(unlock(db, bloromo)?.expect("unlocks").cipher(), Cipher::XChaCha20Poly1305);
+
+        // Switching cipher re-encrypts entries, trashed ones included, under
+        // the same vault key.
+        sleddb::insert_entry(db, &vault_key, "cipher-test", b"secret")?;
+        sleddb::insert_entry(db, &vault_key, "trashed", b"binned")?;
+        assert!(sleddb::trash_entry(db, "trashed", entry::now())?);
+        assert_eq!(set_cipher(db, bloromo, Cipher::Aes256Gcm)?, Some(2));
+        assert_eq!(set_cipher(db, &MasterKey::new(b"Floroma", None)?, Cipher::Aes256Gcm)?, None);
+        assert_eq!(vault_cipher(db)?, Cipher::Aes256Gcm);
+        let vault_key = unlock(db, bloromo)?.expect("unlocks");
+        assert_eq!(vault_key.cipher(), Cipher::Aes256Gcm);
+        let blob = sleddb::entries(db)?.get("cipher-test")?.expect("entry exists");
+        assert!(is_current_with(&blob, Cipher::Aes256Gcm));
+        let context = entry_context(&sleddb::vault_id(db)?, "cipher-test");
+        assert_eq!(&decrypt_entry(&blob, &vault_key, &context)?[..], b"secret");
+
+        assert!(sleddb::untrash_entry(db, "trashed")?);
+        let blob = sleddb::entries(db)?.get("trashed")?.expect("entry restored");
+        assert!(is_current_with(&blob, Cipher::Aes256Gcm));
+        assert_eq!(&sleddb::get_entry(db, &vault_key, "trashed")?.expect("entry restored")[..], b"binned");
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_kdf_params() -> Result<(), anyhow::Error> {
+        let db = &sleddb::temporary();
+        let bloromo = &MasterKey::new(b"Bloromo", None)?;
+        let params = KdfParams::new(8 * 1024, 1, 1)?;
+        store_master_password(db, bloromo, &params, Cipher::default())?;
+        assert_eq!(vault_kdf_params(db)?, params);
+
+        let params = KdfParams::new(16 * 1024, 2, 1)?;
+        assert!(!set_kdf_params(db, &MasterKey::new(b"Floroma", None)?, &params)?);
+        assert!(set_kdf_params(db, bloromo, &params)?);
+        assert_eq!(vault_kdf_params(db)?, params);
+        assert!(unlock(db, bloromo)?.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_legacy_verifier_migration() -> Result<(), anyhow::Error> {
+        let db = &sleddb::temporary();
+        let bloromo = &MasterKey::new(b"Bloromo", None)?;
+        let params = KdfParams::new(8 * 1024, 1, 1)?;
+        store_master_password(db, bloromo, &params, Cipher::default())?;
+
+        // Downgrade to the legacy raw salt/hash verifier and check it migrates.
+        let salt = [5u8; SALT_LEN];
+        let hash = params.derive_key(bloromo.as_bytes(), &salt)?;
+        sleddb::write_batch(db, &[(sleddb::LEGACY_SALT, salt.to_vec()), (sleddb::LEGACY_HASH, hash.to_vec())], &[sleddb::VERIFIER])?;
+        assert!(is_master_password_configured(db)?);
+        assert!(!verify_master_password(db, &MasterKey::new(b"Floroma", None)?)?);
+        assert!(sleddb::get(db, sleddb::VERIFIER)?.is_none());
+        assert!(verify_master_password(db, bloromo)?);
+        assert!(sleddb::get(db, sleddb::VERIFIER)?.is_some());
+        assert!(sleddb::get(db, sleddb::LEGACY_HASH)?.is_none());
+        assert!(verify_master_password(db, bloromo)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_file() -> Result<(), anyhow::Error> {
+        let db = &sleddb::temporary();
+        let bloromo = &MasterKey::new(b"Bloromo", None)?;
+        store_master_password(db, bloromo, &KdfParams::new(8 * 1024, 1, 1)?, Cipher::default())?;
+        assert!(!requires_key_file(db)?);
+
+        // Adding a key file makes it required alongside the password.
+        let key_file = std::env::temp_dir().join(format!("rustpass-test-{}.key", std::process::id()));
+        std::fs::write(&key_file, b"key file contents")?;
+        let with_key_file = &MasterKey::new(b"Bloromo", Some(&key_file))?;
+        assert!(change_master_password(db, bloromo, with_key_file)?);
+        assert!(requires_key_file(db)?);
+        assert!(!verify_master_password(db, bloromo)?);
+        assert!(unlock(db, with_key_file)?.is_some());
+        std::fs::write(&key_file, b"other contents")?;
+        assert!(!verify_master_password(db, &MasterKey::new(b"Bloromo", Some(&key_file))?)?);
+        std::fs::remove_file(&key_file)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_change_master_password() -> Result<(), anyhow::Error> {
+        let db = &sleddb::temporary();
+        let password = &MasterKey::new(b"Floroma", None)?;
+        let bloromo = &MasterKey::new(b"Bloromo", None)?;
+        store_master_password(db, password, &KdfParams::new(8 * 1024, 1, 1)?, Cipher::XChaCha20Poly1305)?;
+
+        assert!(!change_master_password(db, bloromo, &MasterKey::new(b"Shakalaka", None)?)?);
+        assert!(change_master_password(db, password, bloromo)?);
+        assert!(verify_master_password(db, bloromo)?);
+        assert!(!verify_master_password(db, password)?);
+        assert_eq!(unlock(db, bloromo)?.expect("unlocks").cipher(), Cipher::XChaCha20Poly1305);
+        assert!(unlock(db, password)?.is_none());
+        Ok(())
+    }
+
     #[test]
     fn test_password_verificaiton()-> Result<(),anyhow::Error>{
-        let password = b"Floroma";
-        store_master_password(password).expect("unable to store password");
-        let verified = verify_master_password(password).expect("unable to verify password");
-        assert_eq!(verified,true);
+        let db = &sleddb::temporary();
+        let password = &MasterKey::new(b"Floroma", None)?;
+        store_master_password(db, password, &KdfParams::new(8 * 1024, 1, 1)?, Cipher::default()).expect("unable to store password");
+        let verified = verify_master_password(db, password).expect("unable to verify password");
+        assert!(verified);
 
-        let verified = verify_master_password(b"Bloromo").expect("unable to verify password");
-        assert_eq!(verified,false);
+        let verified = verify_master_password(db, &MasterKey::new(b"Bloromo", None)?).expect("unable to verify password");
+        assert!(!verified);
 
         Ok(())
 
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_hostile_verifier_params() -> Result<(), anyhow::Error> {
+        let db = &sleddb::temporary();
+        let password = &MasterKey::new(b"Floroma", None)?;
+        store_master_password(db, password, &KdfParams::new(8 * 1024, 1, 1)?, Cipher::default())?;
+        let verifier = |m_cost: u32| format!("$argon2id$v=19$m={},t=1,p=1$c2FsdHNhbHRzYWx0$AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA", m_cost);
+        sleddb::insert(db, sleddb::VERIFIER, verifier(8 * 1024).as_bytes())?;
+        assert!(!verify_master_password(db, password)?);
+        sleddb::insert(db, sleddb::VERIFIER, verifier(4194304).as_bytes())?;
+        assert!(matches!(verify_master_password(db, password), Err(Error::Corrupted(_))));
+        Ok(())
+    }
+}
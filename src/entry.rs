@@ -0,0 +1,429 @@
+//! Structured vault entries. Each entry is a record with a title, username,
+//! password, URLs, notes, custom fields and an optional one-time password seed, serialized as JSON and encrypted
+//! as one blob under a random entry ID. Titles are unique within a vault.
+//! Changing an entry's password keeps the old one in the entry's history,
+//! and deleted entries stay in the trash for [`TRASH_RETENTION`] seconds.
+//! Older versions stored entries keyed by title next to the vault metadata,
+//! some holding just the password; they are moved under new IDs as records
+//! the first time the vault is unlocked.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use sled::Db;
+
+use crate::encrypt_decrypt::{self, Header, VaultKey};
+use crate::error::Error;
+use crate::otp::Otp;
+use crate::secret::{SecretBytes, SecretString};
+use crate::sleddb;
+
+/// A user defined field, such as a PIN or a security question. Hidden fields
+/// are masked when the entry is shown.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct CustomField {
+    pub name: String,
+    pub value: SecretString,
+    #[serde(default)]
+    pub hidden: bool,
+}
+
+/// A password an entry used before.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct PasswordVersion {
+    pub password: SecretString,
+    /// When this password was replaced, in seconds since the Unix epoch.
+    pub replaced: u64,
+}
+
+/// Number of previous passwords kept per entry.
+pub const MAX_HISTORY: usize = 32;
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Entry {
+    pub title: String,
+    #[serde(default)]
+    pub username: String,
+    pub password: SecretString,
+    #[serde(default)]
+    pub urls: Vec<String>,
+    #[serde(default)]
+    pub notes: SecretString,
+    #[serde(default)]
+    pub fields: Vec<CustomField>,
+    /// One-time password seed, an `otpauth://` URI or base32 secret, or
+    /// empty if the entry has none.
+    #[serde(default)]
+    pub otp: SecretString,
+    /// Seconds since the Unix epoch, or 0 if unknown.
+    #[serde(default)]
+    pub created: u64,
+    /// Seconds since the Unix epoch, or 0 if unknown.
+    #[serde(default)]
+    pub modified: u64,
+    /// Previous passwords, newest first.
+    #[serde(default)]
+    pub history: Vec<PasswordVersion>,
+}
+
+/// An entry ID with its decoded entry, or the reason it could not be read.
+pub type LoadedEntry = (String, Result<Entry, Error>);
+
+/// A deleted entry's ID, deletion time and decoded entry.
+pub type TrashedEntry = (String, u64, Result<Entry, Error>);
+
+/// How long deleted entries are kept in the trash: 30 days, in seconds.
+pub const TRASH_RETENTION: u64 = 30 * 24 * 60 * 60;
+
+impl Entry {
+    /// When the current password was set: when the previous one was
+    /// replaced, or when the entry was created. 0 if unknown.
+    pub fn password_changed(&self) -> u64 {
+        self.history.first().map_or(self.created, |version| version.replaced)
+    }
+
+    pub fn encode(&self) -> Result<SecretBytes, Error> {
+        serde_json::to_vec(self)
+            .map(SecretBytes::new)
+            .map_err(|e| Error::Corrupted(format!("cannot encode entry {}: {}", self.title, e)))
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, Error> {
+        serde_json::from_slice(bytes).map_err(|e| Error::Corrupted(format!("entry is not a valid record: {}", e)))
+    }
+
+    /// Decodes an entry stored by an older version under `key`, which is
+    /// either a record or just the password.
+    fn decode_legacy(key: &str, bytes: &[u8]) -> Result<Self, Error> {
+        if let Ok(entry) = Entry::decode(bytes) {
+            return Ok(entry);
+        }
+        let password = std::str::from_utf8(bytes)
+            .map_err(|_| Error::Corrupted(format!("entry {} is neither a record nor a text password", key)))?;
+        Ok(Entry { title: key.to_string(), password: SecretString::from(password), ..Entry::default() })
+    }
+}
+
+/// Current time in seconds since the Unix epoch.
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// Formats seconds since the Unix epoch as `YYYY-MM-DD HH:MM UTC`, or
+/// `unknown` for 0.
+pub fn format_timestamp(secs: u64) -> String {
+    if secs == 0 {
+        return "unknown".to_string();
+    }
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let minutes = secs % 86_400 / 60;
+    format!("{:04}-{:02}-{:02} {:02}:{:02} UTC", year, month, day, minutes / 60, minutes % 60)
+}
+
+/// Converts days since 1970-01-01 to a proleptic Gregorian date, using
+/// Howard Hinnant's `civil_from_days`.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Decrypts and decodes every entry. Entries that cannot be read are
+/// returned with their error.
+pub fn list_entries(db: &Db, vault_key: &VaultKey) -> Result<Vec<LoadedEntry>, Error> {
+    Ok(sleddb::iter_get_passwords(db, vault_key)?
+        .into_iter()
+        .map(|(id, value)| (id, value.and_then(|value| Entry::decode(&value))))
+        .collect())
+}
+
+/// Loads the entry stored under `id`, or returns `None` if there is none.
+pub fn load_entry(db: &Db, vault_key: &VaultKey, id: &str) -> Result<Option<Entry>, Error> {
+    sleddb::get_entry(db, vault_key, id)?
+        .map(|value| Entry::decode(&value))
+        .transpose()
+}
+
+/// Finds the entry titled `title`, returning its ID and the entry.
+pub fn find_by_title(db: &Db, vault_key: &VaultKey, title: &str) -> Result<Option<(String, Entry)>, Error> {
+    for (id, entry) in list_entries(db, vault_key)? {
+        if let Ok(entry) = entry {
+            if entry.title == title {
+                return Ok(Some((id, entry)));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Stores `entry` and updates its timestamps. `id` is the ID the entry was
+/// loaded from, or `None` for a new entry. The stored entry's history is
+/// kept, and if the password changed the old one is added to it, so callers
+/// never need to carry the history themselves. Returns the entry's ID.
+pub fn save_entry(db: &Db, vault_key: &VaultKey, id: Option<&str>, entry: &mut Entry) -> Result<String, Error> {
+    if entry.title.is_empty() {
+        return Err(Error::InvalidInput("the title cannot be empty".to_string()));
+    }
+    if let Some((other, _)) = find_by_title(db, vault_key, &entry.title)? {
+        if id != Some(other.as_str()) {
+            return Err(Error::InvalidInput(format!("an entry titled {} already exists", entry.title)));
+        }
+    }
+    if !entry.otp.is_empty() {
+        Otp::parse(entry.otp.as_str())?;
+    }
+
+    let now = now();
+    match id.map(|id| load_entry(db, vault_key, id)).transpose()?.flatten() {
+        Some(stored) => {
+            entry.history = stored.history;
+            if stored.password != entry.password {
+                entry.history.insert(0, PasswordVersion { password: stored.password, replaced: now });
+                entry.history.truncate(MAX_HISTORY);
+            }
+        }
+        None => entry.created = now,
+    }
+    entry.modified = now;
+    let id = match id {
+        Some(id) => id.to_string(),
+        None => sleddb::new_entry_id()?,
+    };
+    sleddb::insert_entry(db, vault_key, &id, &entry.encode()?)?;
+    Ok(id)
+}
+
+/// Makes version `version` of the history, 0 being the newest, the current
+/// password again. The password it replaces is added to the history.
+pub fn restore_password(db: &Db, vault_key: &VaultKey, id: &str, version: usize) -> Result<(), Error> {
+    let mut entry = load_entry(db, vault_key, id)?
+        .ok_or_else(|| Error::InvalidInput("no such entry".to_string()))?;
+    let restored = entry.history.get(version)
+        .ok_or_else(|| Error::InvalidInput(format!("{} has no password version {}", entry.title, version + 1)))?;
+    entry.password = restored.password.clone();
+    save_entry(db, vault_key, Some(id), &mut entry)?;
+    Ok(())
+}
+
+/// Moves the entry `id` to the trash. Returns false if there is no such entry.
+pub fn delete_entry(db: &Db, id: &str) -> Result<bool, Error> {
+    sleddb::trash_entry(db, id, now())
+}
+
+/// Decrypts and decodes every entry in the trash, most recently deleted first.
+pub fn list_trash(db: &Db, vault_key: &VaultKey) -> Result<Vec<TrashedEntry>, Error> {
+    let mut trash: Vec<TrashedEntry> = sleddb::iter_trash(db, vault_key)?
+        .into_iter()
+        .map(|(id, deleted, value)| (id, deleted, value.and_then(|value| Entry::decode(&value))))
+        .collect();
+    trash.sort_by_key(|(_, deleted, _)| std::cmp::Reverse(*deleted));
+    Ok(trash)
+}
+
+/// Moves the entry `id` out of the trash. Fails if another entry has taken
+/// its title in the meantime.
+pub fn restore_entry(db: &Db, vault_key: &VaultKey, id: &str, entry: &Entry) -> Result<(), Error> {
+    if find_by_title(db, vault_key, &entry.title)?.is_some() {
+        return Err(Error::InvalidInput(format!("an entry titled {} already exists, rename it first", entry.title)));
+    }
+    if !sleddb::untrash_entry(db, id)? {
+        return Err(Error::InvalidInput(format!("{} is not in the trash", entry.title)));
+    }
+    Ok(())
+}
+
+/// Permanently deletes entries that have been in the trash longer than
+/// [`TRASH_RETENTION`]. Returns the number purged.
+pub fn purge_expired_trash(db: &Db) -> Result<usize, Error> {
+    sleddb::purge_trash_before(db, now().saturating_sub(TRASH_RETENTION))
+}
+
+/// Moves entries stored by older versions under their title into the
+/// entries tree as records. Bare passwords become records titled with their
+/// key, and blobs from before the vault key existed are decrypted with the
+/// master password. Entries that cannot be decrypted are left where they are
+/// and retried on the next unlock. Returns the number of entries moved.
+pub fn migrate_legacy_entries(db: &Db, password: &[u8], vault_key: &VaultKey) -> Result<usize, Error> {
+    let vault_id = sleddb::vault_id(db)?;
+    sleddb::migrate_legacy_entries(db, |key, id, blob| {
+        let plaintext = match Header::parse(blob) {
+            Ok(Some(_)) => encrypt_decrypt::decrypt_with_key(blob, vault_key, &encrypt_decrypt::entry_context(&vault_id, key)),
+            Ok(None) => encrypt_decrypt::decrypt(blob, password),
+            Err(e) => Err(e),
+        };
+        let Ok(entry) = plaintext.and_then(|plaintext| Entry::decode_legacy(key, &plaintext)) else {
+            return Ok(None);
+        };
+        sleddb::seal_entry(db, vault_key, id, &entry.encode()?).map(Some)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encrypt_decrypt::{self, MasterKey};
+
+    fn sample() -> Entry {
+        Entry {
+            title: "Gmail".to_string(),
+            username: "me@example.com".to_string(),
+            password: SecretString::from("hunter2"),
+            urls: vec!["https://mail.google.com".to_string()],
+            notes: SecretString::from("Recovery phone is the old one"),
+            fields: vec![CustomField { name: "PIN".to_string(), value: SecretString::from("1234"), hidden: true }],
+            otp: SecretString::from("otpauth://totp/Google:me%40example.com?secret=GEZDGNBVGY3TQOJQ&issuer=Google"),
+            created: 1_700_000_000,
+            modified: 1_700_000_000,
+            history: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_entry_encoding() -> Result<(), Error> {
+        let entry = sample();
+        assert_eq!(Entry::decode(&entry.encode()?)?, entry);
+        assert!(matches!(Entry::decode(b"hunter2"), Err(Error::Corrupted(_))));
+
+        let legacy = Entry::decode_legacy("Bank", b"{not json")?;
+        assert_eq!(legacy.title, "Bank");
+        assert_eq!(legacy.password.as_str(), "{not json");
+        assert!(legacy.username.is_empty() && legacy.fields.is_empty());
+        assert_eq!(Entry::decode_legacy("Empty", b"{}")?.password.as_str(), "{}");
+        assert_eq!(Entry::decode_legacy("Gmail", &entry.encode()?)?, entry);
+        assert!(matches!(Entry::decode_legacy("Binary", &[0xff, 0xfe]), Err(Error::Corrupted(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "unknown");
+        assert_eq!(format_timestamp(1), "1970-01-01 00:00 UTC");
+        assert_eq!(format_timestamp(1_700_000_000), "2023-11-14 22:13 UTC");
+        assert_eq!(format_timestamp(951_782_400), "2000-02-29 00:00 UTC");
+    }
+
+    #[test]
+    fn test_save_entries() -> Result<(), Error> {
+        let (db, vault_key) = &encrypt_decrypt::temporary_vault();
+
+        let mut entry = sample();
+        let id = save_entry(db, vault_key, None, &mut entry)?;
+        assert!(entry.created > 1_700_000_000 && entry.modified == entry.created);
+        assert!(matches!(save_entry(db, vault_key, None, &mut sample()), Err(Error::InvalidInput(_))));
+        let mut bad_otp = Entry { title: "Bank".to_string(), otp: SecretString::from("otpauth://totp/Bank"), ..sample() };
+        assert!(matches!(save_entry(db, vault_key, None, &mut bad_otp), Err(Error::InvalidInput(_))));
+
+        // Renaming keeps the ID.
+        entry.title = "Google".to_string();
+        assert_eq!(save_entry(db, vault_key, Some(&id), &mut entry)?, id);
+        assert!(find_by_title(db, vault_key, "Gmail")?.is_none());
+        assert_eq!(find_by_title(db, vault_key, "Google")?.expect("renamed").0, id);
+
+        // Titles that were once metadata keys are ordinary titles.
+        for title in [sleddb::LEGACY_HASH, sleddb::LEGACY_SALT, sleddb::VERIFIER] {
+            save_entry(db, vault_key, None, &mut Entry { title: title.to_string(), ..sample() })?;
+        }
+        assert_eq!(list_entries(db, vault_key)?.len(), 4);
+        assert!(encrypt_decrypt::unlock(db, &MasterKey::new(b"Bloromo", None)?)?.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn test_unlock_migrates_legacy_entries() -> Result<(), Error> {
+        let (db, vault_key) = &encrypt_decrypt::temporary_vault();
+        let vault_id = sleddb::vault_id(db)?;
+        let legacy = |key: &str, value: &[u8]| encrypt_decrypt::encrypt_with_key(value, vault_key, &encrypt_decrypt::entry_context(&vault_id, key));
+
+        db.insert("Bank", legacy("Bank", b"old password")?)?;
+        db.insert("Gmail", legacy("Gmail", &sample().encode()?)?)?;
+        db.insert("Broken", legacy("Broken", b"x")?.into_iter().rev().collect::<Vec<u8>>())?;
+        assert!(encrypt_decrypt::unlock(db, &MasterKey::new(b"Bloromo", None)?)?.is_some());
+        assert_eq!(list_entries(db, vault_key)?.len(), 2);
+
+        let bank = find_by_title(db, vault_key, "Bank")?.expect("migrated");
+        assert_eq!(bank.1.password.as_str(), "old password");
+        assert_eq!(bank.1.created, 0);
+        assert_eq!(find_by_title(db, vault_key, "Gmail")?.expect("migrated").1, sample());
+        assert!(db.contains_key("Broken")? && !db.contains_key("Bank")?);
+        assert!(encrypt_decrypt::unlock(db, &MasterKey::new(b"Bloromo", None)?)?.is_some());
+        assert_eq!(list_entries(db, vault_key)?.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_password_history() -> Result<(), Error> {
+        let (db, vault_key) = &encrypt_decrypt::temporary_vault();
+        let id = save_entry(db, vault_key, None, &mut sample())?;
+
+        // Editing other fields does not add a version.
+        let mut entry = Entry { username: "other@example.com".to_string(), ..sample() };
+        save_entry(db, vault_key, Some(&id), &mut entry)?;
+        assert!(entry.history.is_empty());
+
+        for password in ["second", "third"] {
+            let mut entry = Entry { password: SecretString::from(password), ..sample() };
+            save_entry(db, vault_key, Some(&id), &mut entry)?;
+        }
+        let entry = load_entry(db, vault_key, &id)?.expect("stored");
+        let history: Vec<&str> = entry.history.iter().map(|version| version.password.as_str()).collect();
+        assert_eq!(history, ["second", "hunter2"]);
+        assert!(entry.history.iter().all(|version| version.replaced >= entry.created));
+
+        restore_password(db, vault_key, &id, 1)?;
+        let entry = load_entry(db, vault_key, &id)?.expect("stored");
+        assert_eq!(entry.password.as_str(), "hunter2");
+        let history: Vec<&str> = entry.history.iter().map(|version| version.password.as_str()).collect();
+        assert_eq!(history, ["third", "second", "hunter2"]);
+        assert!(matches!(restore_password(db, vault_key, &id, 3), Err(Error::InvalidInput(_))));
+
+        for i in 0..MAX_HISTORY {
+            let mut entry = Entry { password: SecretString::from(format!("rotated {}", i).as_str()), ..sample() };
+            save_entry(db, vault_key, Some(&id), &mut entry)?;
+        }
+        assert_eq!(load_entry(db, vault_key, &id)?.expect("stored").history.len(), MAX_HISTORY);
+        Ok(())
+    }
+
+    #[test]
+    fn test_trash() -> Result<(), Error> {
+        let (db, vault_key) = &encrypt_decrypt::temporary_vault();
+        let gmail = save_entry(db, vault_key, None, &mut sample())?;
+        let bank = save_entry(db, vault_key, None, &mut Entry { title: "Bank".to_string(), ..sample() })?;
+
+        assert!(delete_entry(db, &gmail)?);
+        assert!(!delete_entry(db, &gmail)?);
+        assert_eq!(list_entries(db, vault_key)?.len(), 1);
+        let trash = list_trash(db, vault_key)?;
+        assert_eq!(trash.len(), 1);
+        let (id, deleted, entry) = &trash[0];
+        assert!(*deleted >= now() - 5);
+        let entry = entry.as_ref().expect("decrypts");
+        assert_eq!(entry.title, "Gmail");
+
+        // A new entry took the title, so restoring must wait until it is renamed.
+        let taken = save_entry(db, vault_key, None, &mut sample())?;
+        assert!(matches!(restore_entry(db, vault_key, id, entry), Err(Error::InvalidInput(_))));
+        sleddb::remove_entry(db, &taken)?;
+        restore_entry(db, vault_key, id, entry)?;
+        assert_eq!(load_entry(db, vault_key, &gmail)?.expect("restored"), *entry);
+        assert!(list_trash(db, vault_key)?.is_empty());
+
+        // Only entries older than the retention period are purged.
+        sleddb::trash_entry(db, &bank, now() - TRASH_RETENTION - 60)?;
+        assert!(delete_entry(db, &gmail)?);
+        assert_eq!(purge_expired_trash(db)?, 1);
+        let trash = list_trash(db, vault_key)?;
+        assert_eq!(trash.len(), 1);
+        assert_eq!(trash[0].0, gmail);
+        sleddb::purge_entry(db, &gmail)?;
+        assert!(list_trash(db, vault_key)?.is_empty());
+        Ok(())
+    }
+}
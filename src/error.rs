@@ -0,0 +1,72 @@
+use std::fmt;
+
+/// Errors raised while encrypting, decrypting or storing vault data.
+#[derive(Debug)]
+pub enum Error {
+    /// The key or password is wrong, or the blob has been tampered with.
+    Authentication,
+    /// The blob is shorter than its format requires.
+    Truncated,
+    /// The blob has an unrecognised magic, version, cipher or KDF id.
+    UnknownFormat(String),
+    /// The key derivation parameters are invalid.
+    Kdf(String),
+    /// Encryption itself failed.
+    Encryption,
+    /// Vault metadata is missing or malformed.
+    Corrupted(String),
+    /// The operating system random number generator failed.
+    Random(rand::rand_core::OsError),
+    /// The underlying sled database failed.
+    Storage(sled::Error),
+    /// Reading a file, such as a key file, failed.
+    Io(std::io::Error),
+    /// User supplied text, such as a recovery key, is malformed.
+    InvalidInput(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Authentication => write!(f, "authentication failed (wrong key or corrupted data)"),
+            Error::Truncated => write!(f, "encrypted blob too short"),
+            Error::UnknownFormat(what) => write!(f, "unknown blob format: {}", what),
+            Error::Kdf(what) => write!(f, "invalid KDF parameters: {}", what),
+            Error::Encryption => write!(f, "unable to encrypt"),
+            Error::Corrupted(what) => write!(f, "corrupted vault: {}", what),
+            Error::Random(e) => write!(f, "random number generator failed: {}", e),
+            Error::Storage(e) => write!(f, "storage error: {}", e),
+            Error::Io(e) => write!(f, "{}", e),
+            Error::InvalidInput(what) => write!(f, "invalid input: {}", what),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Random(e) => Some(e),
+            Error::Storage(e) => Some(e),
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<sled::Error> for Error {
+    fn from(e: sled::Error) -> Self {
+        Error::Storage(e)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<rand::rand_core::OsError> for Error {
+    fn from(e: rand::rand_core::OsError) -> Self {
+        Error::Random(e)
+    }
+}
@@ -0,0 +1,336 @@
+//! Random password generator. A [`Policy`] sets the length, the character
+//! classes to draw from, characters to leave out and whether every class
+//! must appear. Policies can be saved per vault as named profiles, so site
+//! specific rules, such as a short maximum length or a restricted symbol
+//! set, only have to be worked out once.
+
+use std::collections::BTreeMap;
+
+use rand::{rngs::OsRng, TryRngCore};
+use serde::{Deserialize, Serialize};
+use sled::Db;
+
+use crate::encrypt_decrypt::{self, VaultKey};
+use crate::error::Error;
+use crate::secret::SecretString;
+use crate::sleddb;
+
+const LOWERCASE: &str = "abcdefghijklmnopqrstuvwxyz";
+const UPPERCASE: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const DIGITS: &str = "0123456789";
+pub const DEFAULT_SYMBOLS: &str = "!@#$%^&*()-_=+[]{};:,.<>/?~";
+
+/// Characters that are easily confused with one another when read or typed.
+pub const AMBIGUOUS: &str = "Il1|O0o`'\"";
+
+pub const MIN_LENGTH: usize = 4;
+pub const MAX_LENGTH: usize = 1024;
+
+/// Name of the profile used when no other profile matches an entry.
+pub const DEFAULT_PROFILE: &str = "default";
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Policy {
+    pub length: usize,
+    pub lowercase: bool,
+    pub uppercase: bool,
+    pub digits: bool,
+    /// Symbols to draw from. Empty means no symbols.
+    pub symbols: String,
+    /// Leave out [`AMBIGUOUS`] characters.
+    pub exclude_ambiguous: bool,
+    /// Further characters to leave out.
+    pub exclude: String,
+    /// Use at least one character of every enabled class.
+    pub require_each_class: bool,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy {
+            length: 20,
+            lowercase: true,
+            uppercase: true,
+            digits: true,
+            symbols: DEFAULT_SYMBOLS.to_string(),
+            exclude_ambiguous: false,
+            exclude: String::new(),
+            require_each_class: true,
+        }
+    }
+}
+
+impl Policy {
+    /// Characters of each enabled class, after exclusions. Classes left empty
+    /// by the exclusions are dropped.
+    fn classes(&self) -> Vec<Vec<char>> {
+        let enabled = [
+            (self.lowercase, LOWERCASE),
+            (self.uppercase, UPPERCASE),
+            (self.digits, DIGITS),
+            (true, self.symbols.as_str()),
+        ];
+        let mut classes: Vec<Vec<char>> = Vec::new();
+        for (on, chars) in enabled {
+            let class: Vec<char> = chars
+                .chars()
+                .filter(|&c| on && !(self.exclude_ambiguous && AMBIGUOUS.contains(c)) && !self.exclude.contains(c))
+                .filter(|c| !classes.iter().any(|class| class.contains(c)))
+                .collect();
+            if !class.is_empty() {
+                classes.push(class);
+            }
+        }
+        classes
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        if !(MIN_LENGTH..=MAX_LENGTH).contains(&self.length) {
+            return Err(Error::InvalidInput(format!("password length must be {} to {}", MIN_LENGTH, MAX_LENGTH)));
+        }
+        let classes = self.classes();
+        if classes.is_empty() {
+            return Err(Error::InvalidInput("the policy leaves no characters to choose from".to_string()));
+        }
+        if self.require_each_class && classes.len() > self.length {
+            return Err(Error::InvalidInput(format!("{} characters cannot include all {} classes", self.length, classes.len())));
+        }
+        Ok(())
+    }
+
+    /// Short description of the policy, such as `20 chars, a-z, A-Z, 0-9, symbols`.
+    pub fn summary(&self) -> String {
+        let mut parts = vec![format!("{} chars", self.length)];
+        for (on, name) in [(self.lowercase, "a-z"), (self.uppercase, "A-Z"), (self.digits, "0-9")] {
+            if on {
+                parts.push(name.to_string());
+            }
+        }
+        if self.symbols == DEFAULT_SYMBOLS {
+            parts.push("symbols".to_string());
+        } else if !self.symbols.is_empty() {
+            parts.push(format!("symbols {}", self.symbols));
+        }
+        if !self.require_each_class {
+            parts.push("classes optional".to_string());
+        }
+        if self.exclude_ambiguous {
+            parts.push("no ambiguous".to_string());
+        }
+        if !self.exclude.is_empty() {
+            parts.push(format!("excluding {}", self.exclude));
+        }
+        parts.join(", ")
+    }
+}
+
+/// Returns a uniformly random index below `n`, by rejection sampling.
+pub(crate) fn random_index(n: usize) -> Result<usize, Error> {
+    let n = n as u64;
+    let zone = u64::MAX - u64::MAX % n;
+    loop {
+        let value = OsRng.try_next_u64()?;
+        if value < zone {
+            return Ok((value % n) as usize);
+        }
+    }
+}
+
+/// Generates a password following `policy`. When every class is required,
+/// one character of each is placed first and the result is shuffled.
+pub fn generate(policy: &Policy) -> Result<SecretString, Error> {
+    policy.validate()?;
+    let classes = policy.classes();
+    let all: Vec<char> = classes.concat();
+
+    let mut chars: Vec<char> = Vec::with_capacity(policy.length);
+    if policy.require_each_class {
+        for class in &classes {
+            chars.push(class[random_index(class.len())?]);
+        }
+    }
+    while chars.len() < policy.length {
+        chars.push(all[random_index(all.len())?]);
+    }
+    for i in (1..chars.len()).rev() {
+        chars.swap(i, random_index(i + 1)?);
+    }
+
+    let mut password = SecretString::new();
+    for c in chars.iter_mut() {
+        password.push(*c);
+        *c = '\0';
+    }
+    Ok(password)
+}
+
+/// Saved profiles by name.
+pub type Profiles = BTreeMap<String, Policy>;
+
+fn profiles_context(db: &Db) -> Result<Vec<u8>, Error> {
+    Ok(encrypt_decrypt::entry_context(&sleddb::vault_id(db)?, sleddb::GENERATOR_PROFILES))
+}
+
+/// Loads the vault's generator profiles, which are encrypted under the vault key.
+pub fn load_profiles(db: &Db, vault_key: &VaultKey) -> Result<Profiles, Error> {
+    let Some(blob) = sleddb::get(db, sleddb::GENERATOR_PROFILES)? else {
+        return Ok(Profiles::new());
+    };
+    let json = encrypt_decrypt::decrypt_with_key(&blob, vault_key, &profiles_context(db)?)?;
+    serde_json::from_slice(&json).map_err(|e| Error::Corrupted(format!("generator profiles: {}", e)))
+}
+
+fn store_profiles(db: &Db, vault_key: &VaultKey, profiles: &Profiles) -> Result<(), Error> {
+    let json = serde_json::to_vec(profiles).map_err(|e| Error::Corrupted(format!("generator profiles: {}", e)))?;
+    let blob = encrypt_decrypt::encrypt_with_key(&json, vault_key, &profiles_context(db)?)?;
+    sleddb::insert(db, sleddb::GENERATOR_PROFILES, &blob)
+}
+
+/// The profiles blob re-encrypted with `vault_key`'s cipher, or `None` if
+/// there are no profiles or they already use it.
+pub fn reseal_profiles(db: &Db, vault_key: &VaultKey) -> Result<Option<Vec<u8>>, Error> {
+    let Some(blob) = sleddb::get(db, sleddb::GENERATOR_PROFILES)? else {
+        return Ok(None);
+    };
+    if encrypt_decrypt::is_current_with(&blob, vault_key.cipher()) {
+        return Ok(None);
+    }
+    let context = profiles_context(db)?;
+    let json = encrypt_decrypt::decrypt_with_key(&blob, vault_key, &context)?;
+    encrypt_decrypt::encrypt_with_key(&json, vault_key, &context).map(Some)
+}
+
+/// Saves `policy` as the profile `name`, replacing any profile of that name.
+pub fn save_profile(db: &Db, vault_key: &VaultKey, name: &str, policy: &Policy) -> Result<(), Error> {
+    if name.trim().is_empty() {
+        return Err(Error::InvalidInput("profile names cannot be empty".to_string()));
+    }
+    policy.validate()?;
+    let mut profiles = load_profiles(db, vault_key)?;
+    profiles.insert(name.trim().to_string(), policy.clone());
+    store_profiles(db, vault_key, &profiles)
+}
+
+/// Deletes the profile `name`. Returns false if there is none.
+pub fn delete_profile(db: &Db, vault_key: &VaultKey, name: &str) -> Result<bool, Error> {
+    let mut profiles = load_profiles(db, vault_key)?;
+    if profiles.remove(name).is_none() {
+        return Ok(false);
+    }
+    store_profiles(db, vault_key, &profiles)?;
+    Ok(true)
+}
+
+/// Picks the profile for an entry: one named like its title, then one named
+/// like the host of one of its URLs or a parent domain of it, then the
+/// `default` profile. Names are compared ignoring case. Returns the name of
+/// the profile used, or `None` for the built in policy.
+pub fn profile_for<'a>(profiles: &'a Profiles, title: &str, urls: &[String]) -> (Option<&'a str>, Policy) {
+    let find = |wanted: &str| profiles.iter().find(|(name, _)| name.eq_ignore_ascii_case(wanted));
+    let hosts = urls.iter().filter_map(|url| host(url));
+    let candidates = std::iter::once(title.trim())
+        .chain(hosts.flat_map(|host| {
+            // login.example.com, then example.com, but not com.
+            let parents = host.match_indices('.').map(move |(i, _)| &host[i + 1..]).filter(|parent| parent.contains('.'));
+            std::iter::once(host).chain(parents)
+        }))
+        .chain(std::iter::once(DEFAULT_PROFILE));
+    for candidate in candidates {
+        if let Some((name, policy)) = find(candidate) {
+            return (Some(name.as_str()), policy.clone());
+        }
+    }
+    (None, Policy::default())
+}
+
+/// Host part of a URL, with or without a scheme.
+fn host(url: &str) -> Option<&str> {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+    let host = host.split(':').next()?;
+    (!host.is_empty()).then_some(host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encrypt_decrypt::{self, Cipher, MasterKey};
+
+    #[test]
+    fn test_generate_follows_policy() -> Result<(), Error> {
+        let policy = Policy::default();
+        for _ in 0..50 {
+            let password = generate(&policy)?;
+            let password = password.as_str();
+            assert_eq!(password.chars().count(), 20);
+            assert!(password.chars().any(|c| c.is_ascii_lowercase()));
+            assert!(password.chars().any(|c| c.is_ascii_uppercase()));
+            assert!(password.chars().any(|c| c.is_ascii_digit()));
+            assert!(password.chars().any(|c| DEFAULT_SYMBOLS.contains(c)));
+        }
+
+        let site = Policy { length: 8, symbols: "-_".to_string(), exclude_ambiguous: true, exclude: "xyz".to_string(), ..Policy::default() };
+        for _ in 0..50 {
+            let password = generate(&site)?;
+            assert!(password.as_str().chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+            assert!(!password.as_str().chars().any(|c| AMBIGUOUS.contains(c) || "xyz".contains(c)));
+            assert!(password.as_str().chars().any(|c| c == '-' || c == '_'));
+        }
+
+        let pin = Policy { length: 6, lowercase: false, uppercase: false, symbols: String::new(), ..Policy::default() };
+        assert!(generate(&pin)?.as_str().chars().all(|c| c.is_ascii_digit()));
+
+        assert!(matches!(generate(&Policy { length: 3, ..Policy::default() }), Err(Error::InvalidInput(_))));
+        assert!(matches!(generate(&Policy { exclude: DIGITS.to_string(), ..pin.clone() }), Err(Error::InvalidInput(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_random_index_is_uniform() -> Result<(), Error> {
+        let mut counts = [0usize; 3];
+        for _ in 0..3000 {
+            counts[random_index(3)?] += 1;
+        }
+        assert!(counts.iter().all(|&count| (850..1150).contains(&count)), "{:?}", counts);
+        Ok(())
+    }
+
+    #[test]
+    fn test_profiles() -> Result<(), Error> {
+        let (db, vault_key) = &encrypt_decrypt::temporary_vault();
+        assert!(load_profiles(db, vault_key)?.is_empty());
+
+        let bank = Policy { length: 12, symbols: String::new(), ..Policy::default() };
+        save_profile(db, vault_key, "example.com", &bank)?;
+        save_profile(db, vault_key, "default", &Policy { length: 32, ..Policy::default() })?;
+        assert!(matches!(save_profile(db, vault_key, "bad", &Policy { length: 1, ..Policy::default() }), Err(Error::InvalidInput(_))));
+
+        let profiles = load_profiles(db, vault_key)?;
+        let urls = ["https://user@login.Example.com:8443/path".to_string()];
+        assert_eq!(profile_for(&profiles, "My bank", &urls), (Some("example.com"), bank.clone()));
+        assert_eq!(profile_for(&profiles, "EXAMPLE.COM", &[]).0, Some("example.com"));
+        assert_eq!(profile_for(&profiles, "Other", &["other.org".to_string()]).1.length, 32);
+
+        assert!(delete_profile(db, vault_key, "default")?);
+        assert!(!delete_profile(db, vault_key, "default")?);
+        assert_eq!(profile_for(&load_profiles(db, vault_key)?, "Other", &[]), (None, Policy::default()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_profiles_follow_cipher() -> Result<(), anyhow::Error> {
+        let (db, vault_key) = &encrypt_decrypt::temporary_vault();
+        let bank = Policy { length: 12, symbols: String::new(), ..Policy::default() };
+        save_profile(db, vault_key, "example.com", &bank)?;
+
+        let bloromo = &MasterKey::new(b"Bloromo", None)?;
+        encrypt_decrypt::set_cipher(db, bloromo, Cipher::XChaCha20Poly1305)?.expect("right password");
+        let vault_key = encrypt_decrypt::unlock(db, bloromo)?.expect("unlocks");
+        let blob = sleddb::get(db, sleddb::GENERATOR_PROFILES)?.expect("profiles stored");
+        assert!(encrypt_decrypt::is_current_with(&blob, Cipher::XChaCha20Poly1305));
+        assert_eq!(load_profiles(db, &vault_key)?.get("example.com"), Some(&bank));
+        Ok(())
+    }
+}
@@ -0,0 +1,220 @@
+//! Vault health report: which entries have a breached, reused, weak, old or
+//! empty password, or empty custom fields. The report never includes the
+//! passwords themselves, so it can be printed or saved as JSON.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::entry::{self, LoadedEntry};
+use crate::strength;
+
+/// Passwords older than this many days are reported, unless configured
+/// otherwise.
+pub const DEFAULT_MAX_AGE_DAYS: u64 = 365;
+
+/// Passwords scoring below this are reported as weak.
+pub const WEAK_BELOW: u8 = 3;
+
+const SECONDS_PER_DAY: u64 = 86_400;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Issue {
+    /// The password is in the breach index, seen `count` times.
+    Breached { count: u32 },
+    /// Other entries use the same password.
+    Reused { with: Vec<String> },
+    /// The password scores below [`WEAK_BELOW`].
+    Weak { score: u8, label: &'static str },
+    /// The password was set `days` days ago. Entries migrated from older
+    /// versions have no timestamps and are never reported as old.
+    Old { days: u64 },
+    EmptyPassword,
+    /// A custom field has a name but no value.
+    EmptyField { name: String },
+}
+
+impl Issue {
+    pub fn describe(&self) -> String {
+        match self {
+            Issue::Breached { count } => format!("breached, seen {} times", count),
+            Issue::Reused { with } => format!("reused in {}", with.join(", ")),
+            Issue::Weak { label, .. } => format!("{} password", label),
+            Issue::Old { days } => format!("not changed in {} days", days),
+            Issue::EmptyPassword => "empty password".to_string(),
+            Issue::EmptyField { name } => format!("empty field {}", name),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct EntryReport {
+    pub id: String,
+    pub title: String,
+    pub issues: Vec<Issue>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Summary {
+    pub entries: usize,
+    pub healthy: usize,
+    pub breached: usize,
+    pub reused: usize,
+    pub weak: usize,
+    pub old: usize,
+    /// Entries with an empty password or custom field.
+    pub empty: usize,
+    pub unreadable: usize,
+    /// Percentage of readable entries without issues, 100 for an empty vault.
+    pub score: u8,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Report {
+    pub summary: Summary,
+    pub max_age_days: u64,
+    /// Entries with at least one issue, in the order they were given.
+    pub entries: Vec<EntryReport>,
+    /// IDs of entries that could not be decrypted.
+    pub unreadable: Vec<String>,
+}
+
+/// Analyzes `entries` as of `now`. `breached` maps entry IDs to how often
+/// their password has been seen in breaches.
+pub fn analyze(entries: &[LoadedEntry], breached: &HashMap<String, u32>, max_age_days: u64, now: u64) -> Report {
+    let mut by_password: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, (_, entry)) in entries.iter().enumerate() {
+        if let Ok(entry) = entry {
+            if !entry.password.is_empty() {
+                by_password.entry(entry.password.as_str()).or_default().push(i);
+            }
+        }
+    }
+
+    let mut summary = Summary { entries: entries.len(), ..Summary::default() };
+    let mut reports = Vec::new();
+    let mut unreadable = Vec::new();
+    for (i, (id, entry)) in entries.iter().enumerate() {
+        let Ok(entry) = entry else {
+            unreadable.push(id.clone());
+            continue;
+        };
+        let mut issues = Vec::new();
+        if let Some(&count) = breached.get(id) {
+            issues.push(Issue::Breached { count });
+        }
+        if entry.password.is_empty() {
+            issues.push(Issue::EmptyPassword);
+        } else {
+            let same = &by_password[entry.password.as_str()];
+            if same.len() > 1 {
+                let with = same.iter().filter(|&&j| j != i).filter_map(|&j| entries[j].1.as_ref().ok()).map(|other| other.title.clone()).collect();
+                issues.push(Issue::Reused { with });
+            }
+            let estimate = strength::estimate(entry.password.as_str());
+            if estimate.score < WEAK_BELOW {
+                issues.push(Issue::Weak { score: estimate.score, label: estimate.label() });
+            }
+            let changed = entry.password_changed();
+            let days = now.saturating_sub(changed) / SECONDS_PER_DAY;
+            if changed != 0 && days > max_age_days {
+                issues.push(Issue::Old { days });
+            }
+        }
+        for field in entry.fields.iter().filter(|field| field.value.is_empty()) {
+            issues.push(Issue::EmptyField { name: field.name.clone() });
+        }
+
+        for issue in &issues {
+            match issue {
+                Issue::Breached { .. } => summary.breached += 1,
+                Issue::Reused { .. } => summary.reused += 1,
+                Issue::Weak { .. } => summary.weak += 1,
+                Issue::Old { .. } => summary.old += 1,
+                Issue::EmptyPassword | Issue::EmptyField { .. } => {}
+            }
+        }
+        summary.empty += usize::from(issues.iter().any(|issue| matches!(issue, Issue::EmptyPassword | Issue::EmptyField { .. })));
+        if issues.is_empty() {
+            summary.healthy += 1;
+        } else {
+            reports.push(EntryReport { id: id.clone(), title: entry.title.clone(), issues });
+        }
+    }
+    summary.unreadable = unreadable.len();
+    let readable = summary.entries - summary.unreadable;
+    summary.score = (summary.healthy * 100).checked_div(readable).map_or(100, |score| score as u8);
+    Report { summary, max_age_days, entries: reports, unreadable }
+}
+
+/// Report for the entries as of now.
+pub fn report(entries: &[LoadedEntry], breached: &HashMap<String, u32>, max_age_days: u64) -> Report {
+    analyze(entries, breached, max_age_days, entry::now())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entry::{CustomField, Entry, PasswordVersion};
+    use crate::error::Error;
+    use crate::secret::SecretString;
+
+    const NOW: u64 = 1_800_000_000;
+    const DAY: u64 = SECONDS_PER_DAY;
+
+    fn entry(title: &str, password: &str, changed_days_ago: u64) -> Entry {
+        Entry {
+            title: title.to_string(),
+            password: SecretString::from(password),
+            created: NOW - changed_days_ago * DAY,
+            ..Entry::default()
+        }
+    }
+
+    #[test]
+    fn test_report() {
+        let strong = "blood-ensure-goose-cargo-gate-waste";
+        let mut rotated = entry("Bank", "unique-ribbon-canvas-orbit", 800);
+        rotated.history.push(PasswordVersion { password: SecretString::from("old"), replaced: NOW - 10 * DAY });
+        let mut fields = entry("Router", "another-quiet-meadow-lamp", 1);
+        fields.fields.push(CustomField { name: "PIN".to_string(), value: SecretString::new(), hidden: true });
+        let entries: Vec<LoadedEntry> = vec![
+            ("a".to_string(), Ok(entry("Gmail", strong, 1))),
+            ("b".to_string(), Ok(entry("Work mail", strong, 400))),
+            ("c".to_string(), Ok(entry("Forum", "password", 1))),
+            ("d".to_string(), Ok(rotated)),
+            ("e".to_string(), Ok(entry("Legacy", "", 0))),
+            ("f".to_string(), Ok(fields)),
+            ("g".to_string(), Err(Error::Authentication)),
+            ("h".to_string(), Ok(Entry { created: 0, ..entry("Migrated", "silent-harbor-pepper-violin", 0) })),
+        ];
+        let breached = HashMap::from([("c".to_string(), 9_659_365)]);
+        let report = analyze(&entries, &breached, DEFAULT_MAX_AGE_DAYS, NOW);
+
+        let issues = |title: &str| report.entries.iter().find(|e| e.title == title).map(|e| e.issues.clone());
+        assert_eq!(issues("Gmail"), Some(vec![Issue::Reused { with: vec!["Work mail".to_string()] }]));
+        assert_eq!(
+            issues("Work mail"),
+            Some(vec![Issue::Reused { with: vec!["Gmail".to_string()] }, Issue::Old { days: 400 }])
+        );
+        assert_eq!(
+            issues("Forum"),
+            Some(vec![Issue::Breached { count: 9_659_365 }, Issue::Weak { score: 0, label: "very weak" }])
+        );
+        assert_eq!(issues("Bank"), None);
+        assert_eq!(issues("Migrated"), None);
+        assert_eq!(issues("Legacy"), Some(vec![Issue::EmptyPassword]));
+        assert_eq!(issues("Router"), Some(vec![Issue::EmptyField { name: "PIN".to_string() }]));
+        assert_eq!(report.unreadable, vec!["g".to_string()]);
+
+        let summary = &report.summary;
+        assert_eq!((summary.entries, summary.healthy, summary.unreadable), (8, 2, 1));
+        assert_eq!((summary.breached, summary.reused, summary.weak, summary.old, summary.empty), (1, 2, 1, 1, 2));
+        assert_eq!(summary.score, 28);
+
+        let json = serde_json::to_value(&report).expect("serializes");
+        assert_eq!(json["entries"][2]["issues"][0], serde_json::json!({ "kind": "breached", "count": 9_659_365 }));
+        assert!(!json.to_string().contains(strong));
+    }
+}
@@ -1,71 +1,665 @@
 use crossterm::{
-    event::{self, Event, KeyCode},
+    event::{self, Event, KeyCode, KeyModifiers},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use ratatui::{
     backend::CrosstermBackend,
+    layout::{Constraint, Layout, Rect},
     style::{Color, Style},
-    widgets::{Block, Borders, List, ListItem, Paragraph},
-    Terminal,
+    text::{Line, Text},
+    widgets::{Block, Borders, Gauge, List, ListItem, Paragraph, Wrap},
+    Frame, Terminal,
 };
 
+pub mod breach;
+pub mod cli;
+pub mod config;
 pub mod encrypt_decrypt;
+pub mod entry;
+pub mod error;
+pub mod generator;
+pub mod health;
+pub mod otp;
+pub mod passphrase;
+pub mod recovery;
+pub mod secret;
+pub mod shamir;
 pub mod sleddb;
+pub mod strength;
+pub mod vaults;
 
 use std::{
-    io::stdout
+    collections::HashMap,
+    io::{stdout, Stdout},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
 };
 
-use crate::encrypt_decrypt::encrypt;
+use clap::Parser;
+use sled::Db;
+
+use crate::cli::{BreachAction, Cli, Command, GlobalArgs};
+use crate::config::Config;
+use crate::encrypt_decrypt::{Cipher, KdfParams, MasterKey, VaultKey};
+use crate::entry::{CustomField, Entry, LoadedEntry, TrashedEntry};
+use crate::health::Report;
+use crate::otp::Otp;
+use crate::passphrase::PassphrasePolicy;
+use crate::recovery::RecoveryKey;
+use crate::secret::SecretString;
+use crate::shamir::Share;
+use crate::vaults::{Vaults, DEFAULT_VAULT};
+
+type Tui = Terminal<CrosstermBackend<Stdout>>;
+
+/// Why the TUI for one vault returned.
+enum TuiExit {
+    Quit,
+    SwitchVault,
+}
 
 enum Screen {
     FirstSetup,
+    FirstSetupKeyFile,
+    FirstSetupCipher,
+    OfferRecoveryKey,
+    ShowRecoveryKey,
     Login,
+    LoginKeyFile,
+    RecoverKey,
+    RecoverNewPassword,
+    RecoverConfirmPassword,
     Menu,
-    AddKeyEntry,
-    AddPasswordEntry,
+    EntryForm,
     EditKey,
-    EditPassword,
     DeleteKey,
+    ConfirmDelete,
+    Trash,
+    ConfirmPurge,
+    Health,
     ViewPassword,
+    PasswordHistory,
+    ChangeOldPassword,
+    ChangeNewPassword,
+    ChangeConfirmPassword,
     SuccessMessage(String),
     ErrorMessage(String), 
 }
 
 #[derive(Default)]
 struct Inputs {
-    masterpass_input: String,
-    password_input: String,
-    key_input: String,
+    masterpass_input: SecretString,
+    /// Entropy of the passphrase suggested for the new master password,
+    /// until it is edited.
+    suggested_bits: Option<f64>,
+    key_file_input: String,
+    form: EntryForm,
     edit_key: String,
-    edit_password: String,
     delete_key: String,
-    stored_passwords: Vec<(String, Vec<u8>)>,
+    /// Entry chosen for deletion, with its ID, awaiting confirmation.
+    pending_delete: Option<(String, Entry)>,
+    trash: Vec<TrashedEntry>,
+    old_password: SecretString,
+    new_password: SecretString,
+    confirm_password: SecretString,
+    recovery_input: SecretString,
+    recovery_display: SecretString,
+    recovery_shares: Vec<Share>,
+    stored_passwords: Vec<LoadedEntry>,
+    /// How often each breached entry's password has been seen, by entry ID.
+    breached: HashMap<String, u32>,
+    health: Option<Report>,
+    /// Entry whose password and hidden fields are shown, and until when.
+    revealed: Option<(String, Instant)>,
+    vault_key: Option<VaultKey>,
+    key_file: Option<PathBuf>,
+}
+
+impl Inputs {
+    fn vault_key(&self) -> Result<&VaultKey, anyhow::Error> {
+        self.vault_key.as_ref().ok_or_else(|| anyhow::anyhow!("Vault is locked"))
+    }
+
+    /// How much longer entry `id` stays revealed, or `None` if it is masked.
+    fn reveal_remaining(&self, id: &str) -> Option<Duration> {
+        let (revealed, until) = self.revealed.as_ref()?;
+        (revealed == id).then(|| until.checked_duration_since(Instant::now())).flatten()
+    }
+
+    /// Reveals entry `id` for `timeout`, or masks it again if it is revealed.
+    fn toggle_reveal(&mut self, id: &str, timeout: Duration) {
+        self.revealed = match self.reveal_remaining(id) {
+            Some(_) => None,
+            None => Some((id.to_string(), Instant::now() + timeout)),
+        };
+    }
+
+    /// Creates the vault from the typed master password and optional key file.
+    fn setup(&mut self, db: &Db, key_file: Option<PathBuf>, cipher: Cipher) -> Result<(), anyhow::Error> {
+        let master = MasterKey::new(self.masterpass_input.as_bytes(), key_file.as_deref())?;
+        self.vault_key = Some(encrypt_decrypt::store_master_password(db, &master, &KdfParams::default(), cipher)?);
+        self.masterpass_input.clear();
+        self.suggested_bits = None;
+        self.key_file_input.clear();
+        self.key_file = key_file;
+        Ok(())
+    }
+
+    /// Unlocks the vault with the typed master password and optional key file.
+    /// The typed inputs are cleared whether or not this succeeds.
+    fn login(&mut self, db: &Db, key_file: Option<PathBuf>) -> Result<(), anyhow::Error> {
+        let unlocked = MasterKey::new(self.masterpass_input.as_bytes(), key_file.as_deref())
+            .and_then(|master| encrypt_decrypt::unlock(db, &master));
+        self.masterpass_input.clear();
+        self.key_file_input.clear();
+        match unlocked? {
+            Some(vault_key) => self.vault_key = Some(vault_key),
+            None => return Err(anyhow::anyhow!("Wrong master password")),
+        }
+        self.key_file = key_file;
+        Ok(())
+    }
+
+    /// Takes the typed recovery key or key share. Returns true once enough
+    /// has been entered to recover the vault.
+    fn add_recovery_input(&mut self) -> Result<bool, anyhow::Error> {
+        if self.recovery_shares.is_empty() && RecoveryKey::parse(self.recovery_input.as_str()).is_ok() {
+            return Ok(true);
+        }
+        let share = Share::parse(self.recovery_input.as_str())?;
+        self.recovery_input.clear();
+        self.recovery_shares.push(share);
+        Ok(self.recovery_shares.len() >= self.recovery_shares[0].threshold() as usize)
+    }
+
+    /// Recovers the vault with the typed recovery key, or the collected key
+    /// shares, and sets the typed new master password. Returns `false` if the
+    /// recovery key or shares are wrong.
+    fn recover(&mut self, db: &Db, key_file: Option<PathBuf>) -> Result<bool, anyhow::Error> {
+        let master = MasterKey::new(self.new_password.as_bytes(), key_file.as_deref())?;
+        let recovered = if self.recovery_shares.is_empty() {
+            recovery::recover_vault(db, &RecoveryKey::parse(self.recovery_input.as_str())?, &master)?
+        } else {
+            shamir::recover_vault(db, &self.recovery_shares, &master)?
+        };
+        let Some(vault_key) = recovered else {
+            return Ok(false);
+        };
+        self.vault_key = Some(vault_key);
+        self.key_file = key_file;
+        Ok(true)
+    }
+
+    /// Checks every entry against the breach index `index` and keeps the
+    /// result for the session.
+    fn check_breaches(&mut self, db: &Db, index: Option<&Path>) -> Result<(), anyhow::Error> {
+        let entries = entry::list_entries(db, self.vault_key()?)?;
+        self.breached = breach::find_breaches(index, &entries)?;
+        Ok(())
+    }
+
+    /// Checks the saved entry `id` against the breach index `index`, keeping
+    /// the results for the other entries.
+    fn recheck_breach(&mut self, index: Option<&Path>, id: &str, entry: &Entry) -> Result<(), anyhow::Error> {
+        let loaded = [(id.to_string(), Ok(entry.clone()))];
+        match breach::find_breaches(index, &loaded)?.remove(id) {
+            Some(count) => self.breached.insert(id.to_string(), count),
+            None => self.breached.remove(id),
+        };
+        Ok(())
+    }
+
+    fn clear_recovery(&mut self) {
+        self.recovery_input.clear();
+        self.recovery_shares.clear();
+        self.clear_password_change();
+    }
+
+    /// Key file path typed by the user, if any.
+    fn typed_key_file(&self) -> Option<PathBuf> {
+        let path = self.key_file_input.trim();
+        (!path.is_empty()).then(|| PathBuf::from(path))
+    }
+
+    fn clear_password_change(&mut self) {
+        self.old_password.clear();
+        self.new_password.clear();
+        self.confirm_password.clear();
+    }
+
+    /// Wipes every secret held for the session, including the vault key.
+    fn lock(&mut self) {
+        self.masterpass_input.clear();
+        self.form = EntryForm::default();
+        self.clear_recovery();
+        self.recovery_display.clear();
+        self.stored_passwords.clear();
+        self.breached.clear();
+        self.health = None;
+        self.revealed = None;
+        self.pending_delete = None;
+        self.trash.clear();
+        self.vault_key = None;
+        self.key_file = None;
+    }
+}
+
+/// How often the vault screens are redrawn while no key is pressed.
+const TICK: Duration = Duration::from_millis(250);
+
+/// Labels of the fixed rows of the entry form. Each custom field adds a name
+/// row and a value row after them.
+const FORM_ROWS: [&str; 6] = ["Title", "Username", "Password", "URLs (separated by spaces)", "Notes", "One-time password (otpauth:// URI or base32 seed)"];
+
+/// The add and edit entry form. Every row is edited as text.
+#[derive(Default)]
+struct EntryForm {
+    /// ID of the entry being edited, or `None` when adding one.
+    editing: Option<String>,
+    created: u64,
+    rows: Vec<SecretString>,
+    /// Whether each custom field is hidden.
+    hidden: Vec<bool>,
+    selected: usize,
+    /// Shown in the title instead of the key help, such as a save error.
+    message: Option<String>,
+}
+
+impl EntryForm {
+    fn new() -> Self {
+        EntryForm { rows: vec![SecretString::new(); FORM_ROWS.len()], ..EntryForm::default() }
+    }
+
+    fn edit(id: &str, entry: &Entry) -> Self {
+        let mut rows = vec![
+            SecretString::from(entry.title.as_str()),
+            SecretString::from(entry.username.as_str()),
+            entry.password.clone(),
+            SecretString::from(entry.urls.join(" ").as_str()),
+            entry.notes.clone(),
+            entry.otp.clone(),
+        ];
+        for field in &entry.fields {
+            rows.push(SecretString::from(field.name.as_str()));
+            rows.push(field.value.clone());
+        }
+        EntryForm {
+            editing: Some(id.to_string()),
+            created: entry.created,
+            rows,
+            hidden: entry.fields.iter().map(|field| field.hidden).collect(),
+            selected: 0,
+            message: None,
+        }
+    }
+
+    /// Custom field a row belongs to, and whether it is the field's value row.
+    fn field_of(row: usize) -> Option<(usize, bool)> {
+        row.checked_sub(FORM_ROWS.len()).map(|row| (row / 2, row % 2 == 1))
+    }
+
+    /// Index of the custom field the selected row belongs to, if any.
+    fn selected_field(&self) -> Option<usize> {
+        Self::field_of(self.selected).map(|(field, _)| field)
+    }
+
+    /// Returns true if the row is the value of a hidden custom field.
+    fn is_hidden(&self, row: usize) -> bool {
+        Self::field_of(row).is_some_and(|(field, value)| value && self.hidden[field])
+    }
+
+    fn label(&self, row: usize) -> String {
+        match Self::field_of(row) {
+            None => FORM_ROWS[row].to_string(),
+            Some((field, false)) => format!("Field {} name", field + 1),
+            Some((field, true)) if self.hidden[field] => format!("Field {} value (hidden)", field + 1),
+            Some((field, true)) => format!("Field {} value", field + 1),
+        }
+    }
+
+    fn add_field(&mut self) {
+        self.rows.push(SecretString::new());
+        self.rows.push(SecretString::new());
+        self.hidden.push(false);
+        self.selected = self.rows.len() - 2;
+    }
+
+    fn remove_field(&mut self) {
+        if let Some(field) = self.selected_field() {
+            let start = FORM_ROWS.len() + field * 2;
+            self.rows.drain(start..start + 2);
+            self.hidden.remove(field);
+            self.selected = start.min(self.rows.len() - 1);
+        }
+    }
+
+    fn toggle_hidden(&mut self) {
+        if let Some(field) = self.selected_field() {
+            self.hidden[field] = !self.hidden[field];
+        }
+    }
+
+    /// Builds the entry from the form. Custom fields without a name are dropped.
+    fn to_entry(&self) -> Entry {
+        Entry {
+            title: self.rows[0].as_str().trim().to_string(),
+            username: self.rows[1].as_str().trim().to_string(),
+            password: self.rows[2].clone(),
+            urls: self.rows[3].as_str().split_whitespace().map(String::from).collect(),
+            notes: self.rows[4].clone(),
+            otp: self.rows[5].clone(),
+            fields: self.rows[FORM_ROWS.len()..]
+                .chunks(2)
+                .zip(&self.hidden)
+                .filter(|(pair, _)| !pair[0].as_str().trim().is_empty())
+                .map(|(pair, &hidden)| CustomField { name: pair[0].as_str().trim().to_string(), value: pair[1].clone(), hidden })
+                .collect(),
+            created: self.created,
+            modified: 0,
+            // Kept from the stored entry by `entry::save_entry`.
+            history: Vec::new(),
+        }
+    }
 }
 
-fn add_entry(master_password: &str, key: &str, value: &str) -> Result<(), anyhow::Error> {
-    let output = encrypt(value.as_bytes(), master_password.as_bytes())?;
-    sleddb::insert(key, &output)?;
+/// Masks a secret value without giving away its length.
+const MASK: &str = "********";
+
+/// Text shown for a stored entry in the view screen. The password and hidden
+/// fields are masked unless `reveal` is set, and the notes if `copy_only` is.
+fn entry_details(entry: &Entry, reveal: bool, copy_only: bool) -> Text<'static> {
+    let password = if reveal { entry.password.as_str() } else { MASK };
+    let mut lines = vec![
+        Line::from(format!("Username: {}", entry.username)),
+        Line::from(format!("Password: {}", password)),
+    ];
+    for url in &entry.urls {
+        lines.push(Line::from(format!("URL: {}", url)));
+    }
+    for field in &entry.fields {
+        let value = if field.hidden && !reveal { MASK } else { field.value.as_str() };
+        lines.push(Line::from(format!("{}: {}", field.name, value)));
+    }
+    if !entry.notes.is_empty() && copy_only {
+        lines.push(Line::from(format!("Notes: {}", MASK)));
+    } else if !entry.notes.is_empty() {
+        lines.push(Line::from("Notes:"));
+        lines.extend(entry.notes.as_str().lines().map(|line| Line::from(line.to_string())));
+    }
+    lines.push(Line::from(""));
+    lines.push(Line::from(format!("Created: {}", entry::format_timestamp(entry.created))));
+    lines.push(Line::from(format!("Modified: {}", entry::format_timestamp(entry.modified))));
+    Text::from(lines)
+}
+
+fn restore_terminal() -> Result<(), anyhow::Error> {
+    disable_raw_mode()?;
+    execute!(stdout(), LeaveAlternateScreen)?;
     Ok(())
 }
 
+fn data_vaults() -> Result<Vaults, anyhow::Error> {
+    Vaults::in_data_dir().ok_or_else(|| anyhow::anyhow!("No data directory found, pass --vault or set {}", config::VAULT_ENV))
+}
+
+/// Opens the named vault. The default vault is created on first use; any
+/// other name must already exist.
+fn open_named(vaults: &Vaults, name: &str) -> Result<Db, anyhow::Error> {
+    if name != DEFAULT_VAULT {
+        return Ok(vaults.open(name)?);
+    }
+    let path = vaults.path(name)?;
+    config::check_legacy_vault(&path)?;
+    Ok(sleddb::open(&path)?)
+}
+
+/// Opens the vault at an explicit path, or the named vault chosen with
+/// `--name` or used last.
+fn open_vault(global: &GlobalArgs, config: &Config) -> Result<Db, anyhow::Error> {
+    if let Some(path) = config::explicit_vault_path(global.vault.as_deref(), config) {
+        return Ok(sleddb::open(&path)?);
+    }
+    let name = global.name.as_deref().or(config.last_vault.as_deref()).unwrap_or(DEFAULT_VAULT);
+    open_named(&data_vaults()?, name)
+}
+
 fn main() -> Result<(), anyhow::Error> {
+    let cli = Cli::parse();
+    let mut config = Config::load()?;
+    match cli.command {
+        Some(Command::Vault { action }) => return cli::run_vault(action, &data_vaults()?, &mut config, &cli.global),
+        Some(Command::Generate(args)) if !args.uses_vault() => return cli::generate(None, &args, &cli.global),
+        Some(Command::Passphrase(args)) => return cli::passphrase(&args),
+        Some(Command::Breach { action: BreachAction::Index { source, index } }) => return cli::build_breach_index(&source, index, &config),
+        Some(command) => return cli::run(&open_vault(&cli.global, &config)?, command, &cli.global, &config),
+        None => {}
+    }
+
     enable_raw_mode()?;
     execute!(stdout(), EnterAlternateScreen)?;
-    let backend = CrosstermBackend::new(stdout());
-    let mut terminal = Terminal::new(backend)?;
 
-    let mut screen = if encrypt_decrypt::is_master_password_configured()? {
+    // Leave raw mode before the panic message is printed, otherwise the
+    // terminal is unusable after a crash.
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let _ = restore_terminal();
+        default_hook(info);
+    }));
+
+    let result = run_app(&cli.global, &mut config);
+    restore_terminal()?;
+    result
+}
+
+/// Shows the vault picker, unless a vault was given on the command line, and
+/// runs the TUI for the chosen vault until the user quits.
+fn run_app(global: &GlobalArgs, config: &mut Config) -> Result<(), anyhow::Error> {
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
+    let explicit = config::explicit_vault_path(global.vault.as_deref(), config).is_some() || global.name.is_some();
+    let mut pick = !explicit;
+
+    loop {
+        let db = if pick {
+            let vaults = data_vaults()?;
+            let name = if vaults.list()?.is_empty() {
+                DEFAULT_VAULT.to_string()
+            } else {
+                match pick_vault(&mut terminal, &vaults, config.last_vault.as_deref())? {
+                    Some(name) => name,
+                    None => return Ok(()),
+                }
+            };
+            let db = open_named(&vaults, &name)?;
+            config.remember_vault(&name)?;
+            db
+        } else {
+            open_vault(global, config)?
+        };
+
+        match run_tui(&mut terminal, &db, global, config)? {
+            TuiExit::Quit => return Ok(()),
+            TuiExit::SwitchVault => pick = true,
+        }
+    }
+}
+
+/// Vault selection screen. Returns the chosen vault, creating it first if
+/// the user typed a new name, or `None` if the user quit.
+fn pick_vault(terminal: &mut Tui, vaults: &Vaults, last_vault: Option<&str>) -> Result<Option<String>, anyhow::Error> {
+    let mut names = vaults.list()?;
+    let mut selected = last_vault.and_then(|last| names.iter().position(|name| name == last)).unwrap_or(0);
+    let mut new_name: Option<String> = None;
+    let mut error: Option<String> = None;
+
+    loop {
+        terminal.draw(|f| {
+            let size = f.area();
+            if let Some(new_name) = &new_name {
+                let title = match &error {
+                    Some(error) => format!("New vault name ({})", error),
+                    None => "New vault name (letters, digits, - and _)".to_string(),
+                };
+                let block = Paragraph::new(new_name.as_str())
+                    .block(Block::default().title(title).borders(Borders::ALL));
+                f.render_widget(block, size);
+                return;
+            }
+            let items: Vec<_> = names
+                .iter()
+                .map(String::as_str)
+                .chain(std::iter::once("+ New vault"))
+                .enumerate()
+                .map(|(i, item)| {
+                    ListItem::new(item).style(if i == selected {
+                        Style::default().fg(Color::Yellow)
+                    } else {
+                        Style::default()
+                    })
+                })
+                .collect();
+            let list = List::new(items)
+                .block(Block::default().title("Choose a vault (UP and DOWN to navigate, Enter to open, Esc to quit)").borders(Borders::ALL));
+            f.render_widget(list, size);
+        })?;
+
+        if let Event::Key(key) = event::read()? {
+            if let Some(name) = new_name.as_mut() {
+                match key.code {
+                    KeyCode::Esc => { new_name = None; error = None; },
+                    KeyCode::Char(c) => name.push(c),
+                    KeyCode::Backspace => { name.pop(); },
+                    KeyCode::Enter => match vaults.create(name) {
+                        Ok(_) => return Ok(Some(name.clone())),
+                        Err(e) => error = Some(e.to_string()),
+                    },
+                    _ => {}
+                }
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(None),
+                KeyCode::Up => selected = selected.saturating_sub(1),
+                KeyCode::Down => selected = (selected + 1).min(names.len()),
+                KeyCode::Enter if selected < names.len() => return Ok(Some(names.swap_remove(selected))),
+                KeyCode::Enter => new_name = Some(String::new()),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Draws a strength gauge for `password` with feedback under it. `required`
+/// is the lowest score accepted, for master passwords.
+fn render_strength(f: &mut Frame, area: Rect, password: &str, required: Option<u8>) {
+    let [gauge_area, feedback_area] = Layout::vertical([Constraint::Length(3), Constraint::Min(0)]).areas(area);
+    let estimate = strength::estimate(password);
+    let color = match estimate.score {
+        0 => Color::Red,
+        1 => Color::LightRed,
+        2 => Color::Yellow,
+        _ => Color::Green,
+    };
+    let gauge = Gauge::default()
+        .block(Block::default().title("Strength").borders(Borders::ALL))
+        .gauge_style(Style::default().fg(color))
+        .ratio(f64::from(estimate.score + 1) / f64::from(strength::MAX_SCORE + 1))
+        .label(format!("{} (about 10^{:.0} guesses)", estimate.label(), estimate.guesses_log10));
+    f.render_widget(gauge, gauge_area);
+
+    let mut lines = Vec::new();
+    if let Some(required) = required.filter(|&required| estimate.score < required || password.is_empty()) {
+        lines.push(Line::styled(
+            format!("A master password must be at least {}.", strength::SCORE_NAMES[required as usize]),
+            Style::default().fg(Color::Red),
+        ));
+    }
+    if let Some(warning) = estimate.warning {
+        lines.push(Line::styled(warning, Style::default().fg(Color::Yellow)));
+    }
+    lines.extend(estimate.suggestions.iter().map(|suggestion| Line::from(*suggestion)));
+    f.render_widget(Paragraph::new(Text::from(lines)).wrap(Wrap { trim: false }), feedback_area);
+}
+
+/// Key help for revealing masked values. `remaining` is how long they stay
+/// revealed.
+fn reveal_help(copy_only: bool, remaining: Option<Duration>) -> String {
+    match remaining {
+        _ if copy_only => "values are never shown".to_string(),
+        Some(remaining) => format!("shown for {:.0}s, r to hide", remaining.as_secs_f64().ceil()),
+        None => "r to reveal".to_string(),
+    }
+}
+
+/// Draws the entry's current one-time code, with a bar counting down until
+/// a TOTP code changes. With `copy_only` the code itself is masked.
+fn render_otp(f: &mut Frame, area: Rect, otp: &Result<Otp, crate::error::Error>, copy_only: bool) {
+    let otp = match otp {
+        Ok(otp) => otp,
+        Err(e) => {
+            let block = Paragraph::new(Line::styled(e.to_string(), Style::default().fg(Color::Red)))
+                .block(Block::default().title("One-time code").borders(Borders::ALL));
+            f.render_widget(block, area);
+            return;
+        }
+    };
+    let now = entry::now();
+    let code = if copy_only { SecretString::from(MASK) } else { otp.code(now) };
+    let (first, second) = code.as_str().split_at(code.as_str().len() / 2);
+    match (otp.kind, otp.remaining(now)) {
+        (otp::Kind::Totp { period }, Some(remaining)) => {
+            let gauge = Gauge::default()
+                .block(Block::default().title("One-time code (o to copy)").borders(Borders::ALL))
+                .gauge_style(Style::default().fg(if remaining <= 5 { Color::Red } else { Color::Green }))
+                .ratio(remaining as f64 / period as f64)
+                .label(format!("{} {}  ({}s left)", first, second, remaining));
+            f.render_widget(gauge, area);
+        }
+        _ => {
+            let block = Paragraph::new(format!("{} {}", first, second))
+                .block(Block::default().title("One-time code (o to copy and move on to the next code)").borders(Borders::ALL));
+            f.render_widget(block, area);
+        }
+    }
+}
+
+fn copy_to_clipboard(text: &str) -> Result<(), arboard::Error> {
+    arboard::Clipboard::new()?.set_text(text)
+}
+
+/// Draws a master password input with its strength under it.
+fn render_master_password(f: &mut Frame, title: &str, password: &str, required: u8) {
+    let [input_area, strength_area] = Layout::vertical([Constraint::Length(3), Constraint::Min(0)]).areas(f.area());
+    let block = Paragraph::new(password)
+        .block(Block::default().title(title).borders(Borders::ALL));
+    f.render_widget(block, input_area);
+    render_strength(f, strength_area, password, Some(required));
+}
+
+/// Returns true if `password` may be used as a master password.
+fn strong_enough(password: &SecretString, required: u8) -> bool {
+    !password.is_empty() && strength::estimate(password.as_str()).score >= required
+}
+
+fn run_tui(terminal: &mut Tui, db: &Db, global: &GlobalArgs, config: &Config) -> Result<TuiExit, anyhow::Error> {
+    let min_strength = config.min_master_strength();
+    let reveal_timeout = config.reveal_timeout();
+    let breach_index = config.breach_index();
+    let copy_only = config.copy_only();
+    let mut screen = if encrypt_decrypt::is_master_password_configured(db)? {
         Screen::Login
     } else {
         Screen::FirstSetup
     };
 
     let mut input = Inputs::default();
-    let menu_items = vec!["Add Password", "View Password", "Edit Password", "Delete Password", "Exit"];
+    let menu_items = ["Add Entry", "View Entries", "Edit Entry", "Delete Entry", "Trash", "Health Report", "Change Master Password", "Generate Recovery Key", "Revoke Recovery Key", "Switch Vault", "Lock Vault", "Exit"];
     let mut selected = 0;
     let mut view_selected: usize = 0;
+    let mut history_selected: usize = 0;
+    let mut trash_selected: usize = 0;
+    let mut health_selected: usize = 0;
+    let mut cipher_selected: usize = 0;
 
     loop {
         terminal.draw(|f| {
@@ -88,56 +682,313 @@ fn main() -> Result<(), anyhow::Error> {
                         .block(Block::default().title("RustPass Menu").borders(Borders::ALL));
                     f.render_widget(list, size);
                 }
-                Screen::AddPasswordEntry => {
-                    let input_block = Paragraph::new(input.password_input.as_str())
-                        .block(Block::default().title("Enter Password for Key").borders(Borders::ALL));
-                    f.render_widget(input_block, size);
-                }
-                Screen::AddKeyEntry => {
-                    let input_block = Paragraph::new(input.key_input.as_str())
-                        .block(Block::default().title("Enter Key").borders(Borders::ALL));
-                    f.render_widget(input_block, size);
+                Screen::EntryForm => {
+                    let form = &input.form;
+                    let items: Vec<_> = form
+                        .rows
+                        .iter()
+                        .enumerate()
+                        .map(|(i, value)| {
+                            // Rows 2 and 5 hold the password and one-time password seed.
+                            let masked = form.is_hidden(i) || (copy_only && (i == 2 || i == 5));
+                            let value = if masked && !value.is_empty() { MASK.to_string() } else { value.as_str().to_string() };
+                            ListItem::new(format!("{}: {}", form.label(i), value)).style(if i == form.selected {
+                                Style::default().fg(Color::Yellow)
+                            } else {
+                                Style::default()
+                            })
+                        })
+                        .collect();
+                    let action = if form.editing.is_some() { "Edit Entry" } else { "Add Entry" };
+                    let title = match &form.message {
+                        Some(message) => format!("{} ({})", action, message),
+                        None => format!("{} (UP and DOWN to move, Enter to save, Ctrl+G generate password, Ctrl+P passphrase, Ctrl+N add field, Ctrl+D remove field, Ctrl+T hide field, Esc to cancel)", action),
+                    };
+                    let [list_area, strength_area] = Layout::vertical([Constraint::Min(0), Constraint::Length(6)]).areas(size);
+                    let list = List::new(items)
+                        .block(Block::default().title(title).borders(Borders::ALL));
+                    f.render_widget(list, list_area);
+                    render_strength(f, strength_area, form.rows[2].as_str(), None);
                 }
                 Screen::FirstSetup => {
-                    let block = Paragraph::new(input.masterpass_input.as_str())
-                        .block(Block::default().title("Set New Master Password").borders(Borders::ALL));
+                    let title = match input.suggested_bits {
+                        Some(bits) => format!("Set New Master Password (suggested passphrase, {:.0} bits; write it down or press Ctrl+G for another)", bits),
+                        None => "Set New Master Password (Ctrl+G to suggest a passphrase)".to_string(),
+                    };
+                    render_master_password(f, &title, input.masterpass_input.as_str(), min_strength);
+                }
+                Screen::FirstSetupKeyFile => {
+                    let block = Paragraph::new(input.key_file_input.as_str())
+                        .block(Block::default().title("Key file path (optional second factor, Enter to skip)").borders(Borders::ALL));
+                    f.render_widget(block, size);
+                }
+                Screen::FirstSetupCipher => {
+                    let items: Vec<_> = Cipher::ALL
+                        .iter()
+                        .enumerate()
+                        .map(|(i, cipher)| {
+                            let label = match cipher {
+                                Cipher::Aes256Gcm => "AES-256-GCM (fastest with AES hardware support)",
+                                Cipher::XChaCha20Poly1305 => "XChaCha20-Poly1305 (fast everywhere, larger nonces)",
+                            };
+                            ListItem::new(label).style(if i == cipher_selected {
+                                Style::default().fg(Color::Yellow)
+                            } else {
+                                Style::default()
+                            })
+                        })
+                        .collect();
+                    let list = List::new(items)
+                        .block(Block::default().title("Choose the vault cipher (UP and DOWN to choose, Enter to confirm)").borders(Borders::ALL));
+                    f.render_widget(list, size);
+                }
+                Screen::OfferRecoveryKey => {
+                    let block = Paragraph::new("A recovery key can unlock the vault if you forget the master password.\n\nGenerate one now? (y/n)")
+                        .block(Block::default().title("Recovery Key").borders(Borders::ALL));
+                    f.render_widget(block, size);
+                }
+                Screen::ShowRecoveryKey => {
+                    let text = Text::from(vec![
+                        Line::from(input.recovery_display.as_str()).style(Style::default().fg(Color::Yellow)),
+                        Line::from(""),
+                        Line::from("Write this key down and keep it somewhere safe. It will not be shown again."),
+                        Line::from("Anyone holding it can open the vault and set a new master password."),
+                        Line::from(""),
+                        Line::from("(Press Enter to continue)"),
+                    ]);
+                    let block = Paragraph::new(text)
+                        .wrap(Wrap { trim: false })
+                        .block(Block::default().title("Recovery Key").borders(Borders::ALL));
                     f.render_widget(block, size);
                 }
                 Screen::Login => {
                     let block = Paragraph::new(input.masterpass_input.as_str())
-                        .block(Block::default().title("Enter Master Password To Login").borders(Borders::ALL));
+                        .block(Block::default().title("Enter Master Password To Login (Tab to recover with recovery key or key shares)").borders(Borders::ALL));
+                    f.render_widget(block, size);
+                }
+                Screen::RecoverKey => {
+                    let title = match input.recovery_shares.first() {
+                        Some(first) => format!("Enter key share {} of {}", input.recovery_shares.len() + 1, first.threshold()),
+                        None => "Enter recovery key or key share".to_string(),
+                    };
+                    let block = Paragraph::new(input.recovery_input.as_str())
+                        .block(Block::default().title(title).borders(Borders::ALL));
+                    f.render_widget(block, size);
+                }
+                Screen::RecoverNewPassword => {
+                    render_master_password(f, "Enter new master password", input.new_password.as_str(), min_strength);
+                }
+                Screen::RecoverConfirmPassword => {
+                    let block = Paragraph::new(input.confirm_password.as_str())
+                        .block(Block::default().title("Confirm new master password").borders(Borders::ALL));
+                    f.render_widget(block, size);
+                }
+                Screen::LoginKeyFile => {
+                    let block = Paragraph::new(input.key_file_input.as_str())
+                        .block(Block::default().title("Enter key file path").borders(Borders::ALL));
                     f.render_widget(block, size);
                 }
                 Screen::ViewPassword => {
                     let items: Vec<ListItem> = input
                         .stored_passwords
                         .iter().enumerate()
-                        .map(|(i,(key, value))| {
-                            let value_str = String::from_utf8_lossy(value);
-                            ListItem::new(format!("{} : {}", key, value_str)).style(if i==view_selected{
+                        .map(|(i,(id, value))| {
+                            let (mut line, mut style) = match value {
+                                Ok(entry) if entry.username.is_empty() => (entry.title.clone(), Style::default()),
+                                Ok(entry) => (format!("{} ({})", entry.title, entry.username), Style::default()),
+                                Err(e) => (format!("{} : [unreadable: {}]", id, e), Style::default().fg(Color::Red)),
+                            };
+                            if let Some(count) = input.breached.get(id) {
+                                line = format!("{} [BREACHED, seen {} times]", line, count);
+                                style = Style::default().fg(Color::Red);
+                            }
+                            ListItem::new(line).style(if i==view_selected{
                                 Style::default().fg(Color::Yellow)
                             } else {
-                                Style::default()
+                                style
                             } )
                         })
                         .collect();
                     let list = List::new(items)
-                        .block(Block::default().title("Stored Passwords (Esc to go back, UP and DOWN to navigate, Enter to copy to clipboard)").borders(Borders::ALL));
+                        .block(Block::default().title("Stored Passwords (Esc to go back, UP and DOWN to navigate, Enter to copy password to clipboard, o to copy one-time code, h for password history)").borders(Borders::ALL));
+                    let [list_area, details_area] = Layout::vertical([Constraint::Percentage(50), Constraint::Percentage(50)]).areas(size);
+                    f.render_widget(list, list_area);
+
+                    let remaining = match input.stored_passwords.get(view_selected) {
+                        Some((id, _)) if !copy_only => input.reveal_remaining(id),
+                        _ => None,
+                    };
+                    let (details, otp) = match input.stored_passwords.get(view_selected) {
+                        Some((_, Ok(entry))) => (entry_details(entry, remaining.is_some(), copy_only), Some(&entry.otp).filter(|otp| !otp.is_empty()).map(|otp| Otp::parse(otp.as_str()))),
+                        _ => (Text::default(), None),
+                    };
+                    let otp_height = if otp.is_some() { 3 } else { 0 };
+                    let [details_area, otp_area] = Layout::vertical([Constraint::Min(0), Constraint::Length(otp_height)]).areas(details_area);
+                    let block = Paragraph::new(details)
+                        .wrap(Wrap { trim: false })
+                        .block(Block::default().title(format!("Details ({})", reveal_help(copy_only, remaining))).borders(Borders::ALL));
+                    f.render_widget(block, details_area);
+                    if let Some(otp) = &otp {
+                        render_otp(f, otp_area, otp, copy_only);
+                    }
+                }
+                Screen::PasswordHistory => {
+                    let (history, remaining) = match input.stored_passwords.get(view_selected) {
+                        Some((id, Ok(entry))) => (entry.history.as_slice(), input.reveal_remaining(id).filter(|_| !copy_only)),
+                        _ => (&[][..], None),
+                    };
+                    let items: Vec<ListItem> = history
+                        .iter()
+                        .enumerate()
+                        .map(|(i, version)| {
+                            let password = if remaining.is_some() { version.password.as_str() } else { MASK };
+                            let line = format!("{}. replaced {} : {}", i + 1, entry::format_timestamp(version.replaced), password);
+                            ListItem::new(line).style(if i == history_selected {
+                                Style::default().fg(Color::Yellow)
+                            } else {
+                                Style::default()
+                            })
+                        })
+                        .collect();
+                    let title = if history.is_empty() {
+                        "Password History (no previous passwords, Esc to go back)".to_string()
+                    } else {
+                        format!("Password History (Esc to go back, UP and DOWN to navigate, Enter to restore, {})", reveal_help(copy_only, remaining))
+                    };
+                    let list = List::new(items)
+                        .block(Block::default().title(title).borders(Borders::ALL));
                     f.render_widget(list, size);
                 }
                 Screen::EditKey => {
                     let block = Paragraph::new(input.edit_key.as_str())
-                        .block(Block::default().title("Enter key to edit").borders(Borders::ALL));
-                    f.render_widget(block, size);
-                }
-                Screen::EditPassword => {
-                    let block = Paragraph::new(input.edit_password.as_str())
-                        .block(Block::default().title("Enter new edited password").borders(Borders::ALL));
+                        .block(Block::default().title("Enter title of the entry to edit").borders(Borders::ALL));
                     f.render_widget(block, size);
                 }
                 Screen::DeleteKey => {
                     let block = Paragraph::new(input.delete_key.as_str())
-                        .block(Block::default().title("Enter key to delete").borders(Borders::ALL));
+                        .block(Block::default().title("Enter title of the entry to delete").borders(Borders::ALL));
+                    f.render_widget(block, size);
+                }
+                Screen::ConfirmDelete => {
+                    let text = match &input.pending_delete {
+                        Some((_, entry)) => {
+                            let mut lines = vec![
+                                Line::from(format!("Title: {}", entry.title)).style(Style::default().fg(Color::Yellow)),
+                                Line::from(format!("Username: {}", entry.username)),
+                            ];
+                            lines.extend(entry.urls.iter().map(|url| Line::from(format!("URL: {}", url))));
+                            lines.push(Line::from(format!("Modified: {}", entry::format_timestamp(entry.modified))));
+                            lines.push(Line::from(""));
+                            lines.push(Line::from(format!(
+                                "Move this entry to the trash? It can be restored from Trash for {} days. (y/n)",
+                                entry::TRASH_RETENTION / 86_400
+                            )));
+                            Text::from(lines)
+                        }
+                        None => Text::default(),
+                    };
+                    let block = Paragraph::new(text)
+                        .wrap(Wrap { trim: false })
+                        .block(Block::default().title("Delete Entry").borders(Borders::ALL));
+                    f.render_widget(block, size);
+                }
+                Screen::Trash => {
+                    let now = entry::now();
+                    let items: Vec<ListItem> = input
+                        .trash
+                        .iter()
+                        .enumerate()
+                        .map(|(i, (id, deleted, value))| {
+                            let days_left = (deleted + entry::TRASH_RETENTION).saturating_sub(now).div_ceil(86_400);
+                            let (line, style) = match value {
+                                Ok(entry) => (
+                                    format!("{} (deleted {}, purged in {} days)", entry.title, entry::format_timestamp(*deleted), days_left),
+                                    Style::default(),
+                                ),
+                                Err(e) => (format!("{} : [unreadable: {}]", id, e), Style::default().fg(Color::Red)),
+                            };
+                            ListItem::new(line).style(if i == trash_selected {
+                                Style::default().fg(Color::Yellow)
+                            } else {
+                                style
+                            })
+                        })
+                        .collect();
+                    let title = if input.trash.is_empty() {
+                        "Trash (empty, Esc to go back)"
+                    } else {
+                        "Trash (Esc to go back, UP and DOWN to navigate, Enter to restore, p to purge permanently)"
+                    };
+                    let list = List::new(items)
+                        .block(Block::default().title(title).borders(Borders::ALL));
+                    f.render_widget(list, size);
+                }
+                Screen::Health => {
+                    let Some(report) = &input.health else { return };
+                    let summary = &report.summary;
+                    let mut counts = format!(
+                        "Breached: {}  Reused: {}  Weak: {}  Older than {} days: {}  Empty: {}",
+                        summary.breached, summary.reused, summary.weak, report.max_age_days, summary.old, summary.empty
+                    );
+                    if summary.unreadable > 0 {
+                        counts.push_str(&format!("  Unreadable: {}", summary.unreadable));
+                    }
+                    let [summary_area, list_area] = Layout::vertical([Constraint::Length(4), Constraint::Min(0)]).areas(size);
+                    let text = Text::from(vec![
+                        Line::from(format!("Score: {}% of {} entries have no issues", summary.score, summary.entries - summary.unreadable)),
+                        Line::from(counts),
+                    ]);
+                    let block = Paragraph::new(text)
+                        .wrap(Wrap { trim: false })
+                        .block(Block::default().title("Health Report").borders(Borders::ALL));
+                    f.render_widget(block, summary_area);
+
+                    let items: Vec<ListItem> = report
+                        .entries
+                        .iter()
+                        .enumerate()
+                        .map(|(i, entry)| {
+                            let issues: Vec<String> = entry.issues.iter().map(|issue| issue.describe()).collect();
+                            let style = if i == health_selected {
+                                Style::default().fg(Color::Yellow)
+                            } else if entry.issues.iter().any(|issue| matches!(issue, health::Issue::Breached { .. })) {
+                                Style::default().fg(Color::Red)
+                            } else {
+                                Style::default()
+                            };
+                            ListItem::new(format!("{}: {}", entry.title, issues.join("; "))).style(style)
+                        })
+                        .collect();
+                    let title = if report.entries.is_empty() {
+                        "No issues found (Esc to go back)"
+                    } else {
+                        "Issues (Esc to go back, UP and DOWN to navigate, Enter to open the entry)"
+                    };
+                    let list = List::new(items)
+                        .block(Block::default().title(title).borders(Borders::ALL));
+                    f.render_widget(list, list_area);
+                }
+                Screen::ConfirmPurge => {
+                    let title = match input.trash.get(trash_selected) {
+                        Some((_, _, Ok(entry))) => entry.title.clone(),
+                        Some((id, _, Err(_))) => id.clone(),
+                        None => String::new(),
+                    };
+                    let block = Paragraph::new(format!("Permanently delete {}? This cannot be undone. (y/n)", title))
+                        .block(Block::default().title("Purge Entry").borders(Borders::ALL));
+                    f.render_widget(block, size);
+                }
+                Screen::ChangeOldPassword => {
+                    let block = Paragraph::new(input.old_password.as_str())
+                        .block(Block::default().title("Enter current master password").borders(Borders::ALL));
+                    f.render_widget(block, size);
+                }
+                Screen::ChangeNewPassword => {
+                    render_master_password(f, "Enter new master password", input.new_password.as_str(), min_strength);
+                }
+                Screen::ChangeConfirmPassword => {
+                    let block = Paragraph::new(input.confirm_password.as_str())
+                        .block(Block::default().title("Confirm new master password").borders(Borders::ALL));
                     f.render_widget(block, size);
                 }
                 Screen::SuccessMessage(ref message) => {
@@ -155,82 +1006,260 @@ fn main() -> Result<(), anyhow::Error> {
             }
         })?;
 
+        // Wake up regularly even without input, so one-time codes and their
+        // countdown stay current.
+        if !event::poll(TICK)? {
+            continue;
+        }
         if let Event::Key(key) = event::read()? {
             match screen {
                 Screen::Menu => match key.code {
                     KeyCode::Char('q') | KeyCode::Esc => break,
-                    KeyCode::Up => if selected > 0 { selected -= 1 },
-                    KeyCode::Down => if selected < menu_items.len() - 1 { selected += 1 },
+                    KeyCode::Up => selected = selected.saturating_sub(1),
+                    KeyCode::Down => selected = (selected + 1).min(menu_items.len() - 1),
                     KeyCode::Enter => match selected {
-                        0 => { input.password_input.clear(); screen = Screen::AddKeyEntry; },
+                        0 => { input.form = EntryForm::new(); screen = Screen::EntryForm; },
                         1 => screen = {
-                            input.stored_passwords = sleddb::iter_get_passwords(input.masterpass_input.as_bytes())?;
+                            view_selected = 0;
+                            input.revealed = None;
+                            input.stored_passwords = entry::list_entries(db, input.vault_key()?)?;
                             Screen::ViewPassword
                         },
                         2 => screen = Screen::EditKey,
                         3 => screen = Screen::DeleteKey,
-                        4 => break,
+                        4 => {
+                            input.trash = entry::list_trash(db, input.vault_key()?)?;
+                            trash_selected = 0;
+                            screen = Screen::Trash;
+                        },
+                        5 => {
+                            input.stored_passwords = entry::list_entries(db, input.vault_key()?)?;
+                            input.health = Some(health::report(&input.stored_passwords, &input.breached, config.max_password_age_days()));
+                            health_selected = 0;
+                            screen = Screen::Health;
+                        },
+                        6 => { input.clear_password_change(); screen = Screen::ChangeOldPassword; },
+                        7 => {
+                            input.recovery_display = recovery::regenerate_recovery_key(db, input.vault_key()?)?.display();
+                            screen = Screen::ShowRecoveryKey;
+                        },
+                        8 => {
+                            if recovery::has_recovery_key(db)? {
+                                recovery::revoke_recovery_key(db)?;
+                                screen = Screen::SuccessMessage("Recovery key revoked. (Press Enter or Esc to return)".to_string());
+                            } else {
+                                screen = Screen::ErrorMessage("This vault has no recovery key. (Press Enter or Esc to return)".to_string());
+                            }
+                        },
+                        9 => return Ok(TuiExit::SwitchVault),
+                        10 => { input.lock(); screen = Screen::Login; },
+                        11 => break,
                         _ => {}
                     },
                     _ => {}
                 },
-                Screen::AddKeyEntry => match key.code {
-                    KeyCode::Esc => screen = Screen::Menu,
-                    KeyCode::Char(q) => input.key_input.push(q),
-                    KeyCode::Backspace => { input.key_input.pop(); },
-                    KeyCode::Enter => screen = Screen::AddPasswordEntry,
+                Screen::EntryForm => {
+                    let form = &mut input.form;
+                    let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+                    match key.code {
+                        KeyCode::Esc => { input.form = EntryForm::default(); screen = Screen::Menu; },
+                        KeyCode::Up | KeyCode::BackTab => form.selected = form.selected.saturating_sub(1),
+                        KeyCode::Down | KeyCode::Tab => form.selected = (form.selected + 1).min(form.rows.len() - 1),
+                        KeyCode::Char('n') if ctrl => form.add_field(),
+                        KeyCode::Char('d') if ctrl => form.remove_field(),
+                        KeyCode::Char('t') if ctrl => form.toggle_hidden(),
+                        KeyCode::Char('p') if ctrl => {
+                            let policy = PassphrasePolicy::default();
+                            form.message = Some(match passphrase::generate(&policy) {
+                                Ok(passphrase) => {
+                                    form.rows[2] = passphrase;
+                                    form.selected = 2;
+                                    format!("generated a {}-word passphrase, {:.0} bits", policy.words, policy.entropy())
+                                }
+                                Err(e) => e.to_string(),
+                            });
+                        },
+                        KeyCode::Char('g') if ctrl => {
+                            // Uses the profile matching the title or URLs typed so far.
+                            let vault_key = input.vault_key.as_ref().ok_or_else(|| anyhow::anyhow!("Vault is locked"))?;
+                            let entry = form.to_entry();
+                            let generated = generator::load_profiles(db, vault_key).and_then(|profiles| {
+                                let (name, policy) = generator::profile_for(&profiles, &entry.title, &entry.urls);
+                                Ok((format!("generated with the {} profile", name.unwrap_or("built-in")), generator::generate(&policy)?))
+                            });
+                            form.message = Some(match generated {
+                                Ok((message, password)) => {
+                                    form.rows[2] = password;
+                                    form.selected = 2;
+                                    message
+                                }
+                                Err(e) => e.to_string(),
+                            });
+                        },
+                        KeyCode::Char(c) if !ctrl => form.rows[form.selected].push(c),
+                        KeyCode::Backspace => { form.rows[form.selected].pop(); },
+                        KeyCode::Enter => {
+                            let mut entry = form.to_entry();
+                            let vault_key = input.vault_key.as_ref().ok_or_else(|| anyhow::anyhow!("Vault is locked"))?;
+                            match entry::save_entry(db, vault_key, input.form.editing.as_deref(), &mut entry) {
+                                Ok(id) => {
+                                    let message = if input.form.editing.is_some() { "Entry updated successfully!" } else { "Entry added successfully!" };
+                                    input.form = EntryForm::default();
+                                    screen = match input.recheck_breach(breach_index.as_deref(), &id, &entry) {
+                                        Ok(()) => Screen::SuccessMessage(format!("{} (Press Enter or Esc to return)", message)),
+                                        Err(e) => Screen::ErrorMessage(format!("{} Breach check failed: {}. (Press Enter or Esc to return)", message, e)),
+                                    };
+                                }
+                                Err(e) => input.form.message = Some(e.to_string()),
+                            }
+                        },
+                        _ => {}
+                    }
+                },
+                Screen::FirstSetup => match key.code {
+                    KeyCode::Esc => { input.masterpass_input.clear(); break; },
+                    KeyCode::Enter if strong_enough(&input.masterpass_input, min_strength) => {
+                        if global.keyfile.is_some() {
+                            screen = Screen::FirstSetupCipher;
+                        } else {
+                            screen = Screen::FirstSetupKeyFile;
+                        }
+                    },
+                    KeyCode::Char('g') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                        let policy = PassphrasePolicy::default();
+                        input.masterpass_input = passphrase::generate(&policy)?;
+                        input.suggested_bits = Some(policy.entropy());
+                    },
+                    KeyCode::Backspace => { input.masterpass_input.pop(); input.suggested_bits = None; },
+                    KeyCode::Char(q) => { input.masterpass_input.push(q); input.suggested_bits = None; },
                     _ => {}
                 },
-                Screen::AddPasswordEntry => match key.code {
-                    KeyCode::Esc => screen = Screen::Menu,
-                    KeyCode::Char(q) => input.password_input.push(q),
-                    KeyCode::Backspace => { input.password_input.pop(); },
+                Screen::FirstSetupKeyFile => match key.code {
+                    KeyCode::Esc => { input.key_file_input.clear(); screen = Screen::FirstSetup; },
+                    KeyCode::Enter => screen = Screen::FirstSetupCipher,
+                    KeyCode::Backspace => { input.key_file_input.pop(); },
+                    KeyCode::Char(c) => input.key_file_input.push(c),
+                    _ => {}
+                },
+                Screen::FirstSetupCipher => match key.code {
+                    KeyCode::Esc => { input.key_file_input.clear(); screen = Screen::FirstSetup; },
+                    KeyCode::Up => cipher_selected = cipher_selected.saturating_sub(1),
+                    KeyCode::Down => cipher_selected = (cipher_selected + 1).min(Cipher::ALL.len() - 1),
                     KeyCode::Enter => {
-                        add_entry(&input.masterpass_input, &input.key_input, &input.password_input)?;
-                        input.key_input.clear();
-                        input.password_input.clear();
-                        screen = Screen::SuccessMessage("Entry added successfully! (Press Enter or Esc to return)".to_string());
+                        screen = match input.setup(db, global.keyfile.clone().or(input.typed_key_file()), Cipher::ALL[cipher_selected]) {
+                            Ok(()) => Screen::OfferRecoveryKey,
+                            Err(e) => Screen::ErrorMessage(format!("{}. (Press Enter or Esc to return)", e)),
+                        };
                     },
                     _ => {}
                 },
-                Screen::FirstSetup => match key.code {
-                    KeyCode::Esc => { input.masterpass_input.clear(); break; },
-                    KeyCode::Enter => {
-                        encrypt_decrypt::store_master_password(&input.masterpass_input.as_bytes())?;
-                        screen = Screen::Menu;
+                Screen::OfferRecoveryKey => match key.code {
+                    KeyCode::Char('y') | KeyCode::Char('Y') => {
+                        input.recovery_display = recovery::regenerate_recovery_key(db, input.vault_key()?)?.display();
+                        screen = Screen::ShowRecoveryKey;
                     },
-                    KeyCode::Backspace => { input.masterpass_input.pop(); },
-                    KeyCode::Char(q) => input.masterpass_input.push(q),
+                    KeyCode::Char('n') | KeyCode::Char('N') | KeyCode::Esc => screen = Screen::Menu,
+                    _ => {}
+                },
+                Screen::ShowRecoveryKey => match key.code {
+                    KeyCode::Enter | KeyCode::Esc => { input.recovery_display.clear(); screen = Screen::Menu; },
                     _ => {}
                 },
                 Screen::Login => match key.code {
+                    KeyCode::Tab if recovery::has_recovery_key(db)? || shamir::has_shares(db)? => {
+                        input.masterpass_input.clear();
+                        screen = Screen::RecoverKey;
+                    },
                     KeyCode::Enter => {
-                        if !encrypt_decrypt::verify_master_password(&input.masterpass_input.as_bytes())? {
-                            return Err(anyhow::anyhow!("Wrong Password"));
+                        if global.keyfile.is_none() && encrypt_decrypt::requires_key_file(db)? {
+                            screen = Screen::LoginKeyFile;
+                        } else {
+                            screen = match input.login(db, global.keyfile.clone()).and_then(|()| input.check_breaches(db, breach_index.as_deref())) {
+                                Ok(()) => Screen::Menu,
+                                Err(e) => Screen::ErrorMessage(format!("{}. (Press Enter or Esc to return)", e)),
+                            };
                         }
-                        screen = Screen::Menu;
                     },
                     KeyCode::Esc => { input.masterpass_input.clear(); break; },
                     KeyCode::Backspace => { input.masterpass_input.pop(); },
                     KeyCode::Char(c) => input.masterpass_input.push(c),
                     _ => {}
                 },
+                Screen::LoginKeyFile => match key.code {
+                    KeyCode::Esc => { input.key_file_input.clear(); screen = Screen::Login; },
+                    KeyCode::Enter => {
+                        screen = match input.login(db, input.typed_key_file()).and_then(|()| input.check_breaches(db, breach_index.as_deref())) {
+                            Ok(()) => Screen::Menu,
+                            Err(e) => Screen::ErrorMessage(format!("{}. (Press Enter or Esc to return)", e)),
+                        };
+                    },
+                    KeyCode::Backspace => { input.key_file_input.pop(); },
+                    KeyCode::Char(c) => input.key_file_input.push(c),
+                    _ => {}
+                },
+                Screen::RecoverKey => match key.code {
+                    KeyCode::Esc => { input.clear_recovery(); screen = Screen::Login; },
+                    KeyCode::Enter => match input.add_recovery_input() {
+                        Ok(true) => screen = Screen::RecoverNewPassword,
+                        Ok(false) => {}
+                        Err(e) => {
+                            input.clear_recovery();
+                            screen = Screen::ErrorMessage(format!("{}. (Press Enter or Esc to return)", e));
+                        }
+                    },
+                    KeyCode::Backspace => { input.recovery_input.pop(); },
+                    KeyCode::Char(c) => input.recovery_input.push(c),
+                    _ => {}
+                },
+                Screen::RecoverNewPassword => match key.code {
+                    KeyCode::Esc => { input.clear_recovery(); screen = Screen::Login; },
+                    KeyCode::Char(c) => input.new_password.push(c),
+                    KeyCode::Backspace => { input.new_password.pop(); },
+                    KeyCode::Enter if strong_enough(&input.new_password, min_strength) => screen = Screen::RecoverConfirmPassword,
+                    _ => {}
+                },
+                Screen::RecoverConfirmPassword => match key.code {
+                    KeyCode::Esc => { input.clear_recovery(); screen = Screen::Login; },
+                    KeyCode::Char(c) => input.confirm_password.push(c),
+                    KeyCode::Backspace => { input.confirm_password.pop(); },
+                    KeyCode::Enter => {
+                        if input.confirm_password != input.new_password {
+                            screen = Screen::ErrorMessage("Passwords do not match. (Press Enter or Esc to return)".to_string());
+                        } else {
+                            let recovered = input.recover(db, global.keyfile.clone())
+                                .and_then(|recovered| if recovered { input.check_breaches(db, breach_index.as_deref()).map(|()| true) } else { Ok(false) });
+                            screen = match recovered {
+                                Ok(true) => Screen::SuccessMessage("Vault recovered and new master password set. (Press Enter or Esc to return)".to_string()),
+                                Ok(false) => Screen::ErrorMessage("Wrong recovery key or key shares. (Press Enter or Esc to return)".to_string()),
+                                Err(e) => Screen::ErrorMessage(format!("{}. (Press Enter or Esc to return)", e)),
+                            };
+                        }
+                        input.clear_recovery();
+                    },
+                    _ => {}
+                },
                 Screen::ViewPassword => {
                     match key.code{
                        KeyCode::Esc => {
                             input.stored_passwords.clear();
+                            input.revealed = None;
                             screen = Screen::Menu;
                         },
                         KeyCode::Up => {
-                            if view_selected > 0 {
-                                view_selected -= 1;
-                            }
+                            view_selected = view_selected.saturating_sub(1);
                         }
                         KeyCode::Enter => {
-                            if let Some((_, value)) = input.stored_passwords.iter().nth(view_selected) {
+                            if let Some((_, value)) = input.stored_passwords.get(view_selected) {
+                                let value = match value {
+                                    Ok(entry) => entry.password.as_bytes(),
+                                    Err(e) => {
+                                        screen = Screen::ErrorMessage(format!("Entry cannot be decrypted: {}  (Press Enter or Esc to return)", e));
+                                        continue;
+                                    }
+                                };
                                 // Copy to clipboard
                                 if let Ok(mut clipboard) = arboard::Clipboard::new() {
-                                    if let Ok(plaintext) = String::from_utf8(value.clone()) {
+                                    if let Ok(plaintext) = std::str::from_utf8(value) {
                                         if let Err(e) = clipboard.set_text(plaintext) {
                                             screen = Screen::ErrorMessage(format!("Failed to copy: {}", e));
                                         } else {
@@ -245,9 +1274,73 @@ fn main() -> Result<(), anyhow::Error> {
                             }
                         }
                         KeyCode::Down => {
-                            if view_selected < input.stored_passwords.len().saturating_sub(1) {
-                                view_selected += 1;
+                            view_selected = (view_selected + 1).min(input.stored_passwords.len().saturating_sub(1));
+                        },
+                        KeyCode::Char('o') => {
+                            let (id, mut entry) = match input.stored_passwords.get(view_selected) {
+                                Some((id, Ok(entry))) if !entry.otp.is_empty() => (id.clone(), entry.clone()),
+                                Some((_, Ok(entry))) => {
+                                    screen = Screen::ErrorMessage(format!("{} has no one-time password. (Press Enter or Esc to return)", entry.title));
+                                    continue;
+                                }
+                                _ => continue,
+                            };
+                            let mut otp = match Otp::parse(entry.otp.as_str()) {
+                                Ok(otp) => otp,
+                                Err(e) => {
+                                    screen = Screen::ErrorMessage(format!("{}. (Press Enter or Esc to return)", e));
+                                    continue;
+                                }
+                            };
+                            if let Err(e) = copy_to_clipboard(otp.code(entry::now()).as_str()) {
+                                screen = Screen::ErrorMessage(format!("Failed to copy: {}  (Press Enter or Esc to return)", e));
+                                continue;
+                            }
+                            // An HOTP code is used once it is copied.
+                            if let otp::Kind::Hotp { .. } = otp.kind {
+                                otp.advance();
+                                entry.otp = otp.to_uri();
+                                entry::save_entry(db, input.vault_key()?, Some(&id), &mut entry)?;
+                                input.stored_passwords[view_selected].1 = Ok(entry);
                             }
+                            screen = Screen::SuccessMessage("One-time code copied to clipboard!  (Press Enter or Esc to return)".to_string());
+                        },
+                        KeyCode::Char('r') if !copy_only => {
+                            if let Some((id, Ok(_))) = input.stored_passwords.get(view_selected) {
+                                let id = id.clone();
+                                input.toggle_reveal(&id, reveal_timeout);
+                            }
+                        },
+                        KeyCode::Char('h') if matches!(input.stored_passwords.get(view_selected), Some((_, Ok(_)))) => {
+                            history_selected = 0;
+                            screen = Screen::PasswordHistory;
+                        },
+                        _ => {}
+                    }
+                },
+                Screen::PasswordHistory => {
+                    let Some((id, Ok(entry))) = input.stored_passwords.get(view_selected) else {
+                        screen = Screen::ViewPassword;
+                        continue;
+                    };
+                    match key.code {
+                        KeyCode::Esc => screen = Screen::ViewPassword,
+                        KeyCode::Up => history_selected = history_selected.saturating_sub(1),
+                        KeyCode::Down => history_selected = (history_selected + 1).min(entry.history.len().saturating_sub(1)),
+                        KeyCode::Char('r') if !copy_only => {
+                            let id = id.clone();
+                            input.toggle_reveal(&id, reveal_timeout);
+                        },
+                        KeyCode::Enter if history_selected < entry.history.len() => {
+                            let replaced = entry::format_timestamp(entry.history[history_selected].replaced);
+                            let id = id.clone();
+                            entry::restore_password(db, input.vault_key()?, &id, history_selected)?;
+                            let restored = entry::load_entry(db, input.vault_key()?, &id)?.ok_or_else(|| anyhow::anyhow!("Entry not found"))?;
+                            input.stored_passwords.clear();
+                            screen = match input.recheck_breach(breach_index.as_deref(), &id, &restored) {
+                                Ok(()) => Screen::SuccessMessage(format!("Password replaced on {} restored. (Press Enter or Esc to return)", replaced)),
+                                Err(e) => Screen::ErrorMessage(format!("Password restored. Breach check failed: {}. (Press Enter or Esc to return)", e)),
+                            };
                         },
                         _ => {}
                     }
@@ -255,52 +1348,166 @@ fn main() -> Result<(), anyhow::Error> {
                 Screen::EditKey => match key.code {
                     KeyCode::Esc => {
                         input.edit_key.clear();
-                        input.edit_password.clear();
                         screen = Screen::Menu;
                     },
                     KeyCode::Enter => {
-                        if let Some(_) = sleddb::get(&input.edit_key) {
-                            screen = Screen::EditPassword;
-                        } else {
-                            screen = Screen::ErrorMessage("Key not found. (Press Enter or Esc to return)".to_string());
-                        }
+                        screen = match entry::find_by_title(db, input.vault_key()?, &input.edit_key)? {
+                            Some((id, entry)) => {
+                                input.form = EntryForm::edit(&id, &entry);
+                                Screen::EntryForm
+                            }
+                            None => Screen::ErrorMessage("Entry not found. (Press Enter or Esc to return)".to_string()),
+                        };
+                        input.edit_key.clear();
                     },
                     KeyCode::Char(c) => input.edit_key.push(c),
                     KeyCode::Backspace => { input.edit_key.pop(); },
                     _ => {}
                 },
-                Screen::EditPassword => match key.code {
+                Screen::DeleteKey => match key.code {
+                    KeyCode::Esc => screen = Screen::Menu,
+                    KeyCode::Char(c) => input.delete_key.push(c),
+                    KeyCode::Backspace => { input.delete_key.pop(); },
+                    KeyCode::Enter => {
+                        if let Some(found) = entry::find_by_title(db, input.vault_key()?, &input.delete_key)? {
+                            input.pending_delete = Some(found);
+                            screen = Screen::ConfirmDelete;
+                        } else {
+                            screen = Screen::ErrorMessage("Entry not found. (Press Enter or Esc to return)".to_string());
+                        }
+                        input.delete_key.clear();
+                    },
+                    _ => {}
+                },
+                Screen::ConfirmDelete => match key.code {
+                    KeyCode::Char('y') | KeyCode::Char('Y') => {
+                        if let Some((id, entry)) = input.pending_delete.take() {
+                            entry::delete_entry(db, &id)?;
+                            screen = Screen::SuccessMessage(format!("{} moved to the trash. (Press Enter or Esc to return)", entry.title));
+                        }
+                    },
+                    KeyCode::Char('n') | KeyCode::Char('N') | KeyCode::Esc => {
+                        input.pending_delete = None;
+                        screen = Screen::Menu;
+                    },
+                    _ => {}
+                },
+                Screen::Trash => match key.code {
+                    KeyCode::Esc => { input.trash.clear(); screen = Screen::Menu; },
+                    KeyCode::Up => trash_selected = trash_selected.saturating_sub(1),
+                    KeyCode::Down => trash_selected = (trash_selected + 1).min(input.trash.len().saturating_sub(1)),
+                    KeyCode::Enter => match input.trash.get(trash_selected) {
+                        Some((id, _, Ok(entry))) => {
+                            let (id, entry) = (id.clone(), entry.clone());
+                            screen = match entry::restore_entry(db, input.vault_key()?, &id, &entry) {
+                                Ok(()) => match input.recheck_breach(breach_index.as_deref(), &id, &entry) {
+                                    Ok(()) => Screen::SuccessMessage(format!("{} restored. (Press Enter or Esc to return)", entry.title)),
+                                    Err(e) => Screen::ErrorMessage(format!("{} restored. Breach check failed: {}. (Press Enter or Esc to return)", entry.title, e)),
+                                },
+                                Err(e) => Screen::ErrorMessage(format!("{}. (Press Enter or Esc to return)", e)),
+                            };
+                            input.trash.clear();
+                        }
+                        Some((_, _, Err(e))) => {
+                            screen = Screen::ErrorMessage(format!("Entry cannot be decrypted: {}  (Press Enter or Esc to return)", e));
+                            input.trash.clear();
+                        }
+                        None => {}
+                    },
+                    KeyCode::Char('p') if trash_selected < input.trash.len() => screen = Screen::ConfirmPurge,
+                    _ => {}
+                },
+                Screen::Health => match key.code {
                     KeyCode::Esc => {
-                        input.edit_key.clear();
-                        input.edit_password.clear();
+                        input.health = None;
+                        input.stored_passwords.clear();
                         screen = Screen::Menu;
                     },
+                    KeyCode::Up => health_selected = health_selected.saturating_sub(1),
+                    KeyCode::Down => {
+                        let count = input.health.as_ref().map_or(0, |report| report.entries.len());
+                        health_selected = (health_selected + 1).min(count.saturating_sub(1));
+                    },
                     KeyCode::Enter => {
-                        add_entry(&input.masterpass_input, &input.edit_key, &input.edit_password)?;
-                        input.edit_key.clear();
-                        input.edit_password.clear();
-                        screen = Screen::SuccessMessage("Password updated successfully! (Press Enter or Esc to return)".to_string());
+                        let selected_id = input.health.as_ref().and_then(|report| report.entries.get(health_selected)).map(|entry| &entry.id);
+                        if let Some(i) = selected_id.and_then(|selected_id| input.stored_passwords.iter().position(|(id, _)| id == selected_id)) {
+                            view_selected = i;
+                            input.revealed = None;
+                            input.health = None;
+                            screen = Screen::ViewPassword;
+                        }
                     },
-                    KeyCode::Char(c) => input.edit_password.push(c),
-                    KeyCode::Backspace => { input.edit_password.pop(); },
                     _ => {}
                 },
-                Screen::DeleteKey => match key.code {
-                    KeyCode::Esc => screen = Screen::Menu,
-                    KeyCode::Char(c) => input.delete_key.push(c),
-                    KeyCode::Backspace => { input.delete_key.pop(); },
+                Screen::ConfirmPurge => match key.code {
+                    KeyCode::Char('y') | KeyCode::Char('Y') => {
+                        if trash_selected < input.trash.len() {
+                            let (id, _, _) = input.trash.remove(trash_selected);
+                            sleddb::purge_entry(db, &id)?;
+                            trash_selected = trash_selected.min(input.trash.len().saturating_sub(1));
+                        }
+                        screen = Screen::Trash;
+                    },
+                    KeyCode::Char('n') | KeyCode::Char('N') | KeyCode::Esc => screen = Screen::Trash,
+                    _ => {}
+                },
+                Screen::ChangeOldPassword => match key.code {
+                    KeyCode::Esc => { input.clear_password_change(); screen = Screen::Menu; },
+                    KeyCode::Char(c) => input.old_password.push(c),
+                    KeyCode::Backspace => { input.old_password.pop(); },
+                    KeyCode::Enter => {
+                        let verified = MasterKey::new(input.old_password.as_bytes(), input.key_file.as_deref())
+                            .and_then(|old| encrypt_decrypt::verify_master_password(db, &old));
+                        match verified {
+                            Ok(true) => screen = Screen::ChangeNewPassword,
+                            Ok(false) => {
+                                input.clear_password_change();
+                                screen = Screen::ErrorMessage("Wrong master password. (Press Enter or Esc to return)".to_string());
+                            }
+                            Err(e) => {
+                                input.clear_password_change();
+                                screen = Screen::ErrorMessage(format!("{}. (Press Enter or Esc to return)", e));
+                            }
+                        }
+                    },
+                    _ => {}
+                },
+                Screen::ChangeNewPassword => match key.code {
+                    KeyCode::Esc => { input.clear_password_change(); screen = Screen::Menu; },
+                    KeyCode::Char(c) => input.new_password.push(c),
+                    KeyCode::Backspace => { input.new_password.pop(); },
+                    KeyCode::Enter if strong_enough(&input.new_password, min_strength) => screen = Screen::ChangeConfirmPassword,
+                    _ => {}
+                },
+                Screen::ChangeConfirmPassword => match key.code {
+                    KeyCode::Esc => { input.clear_password_change(); screen = Screen::Menu; },
+                    KeyCode::Char(c) => input.confirm_password.push(c),
+                    KeyCode::Backspace => { input.confirm_password.pop(); },
                     KeyCode::Enter => {
-                        if sleddb::get(&input.delete_key).is_some() {
-                            sleddb::remove(&input.delete_key)?;
-                            input.delete_key.clear();
-                            screen = Screen::SuccessMessage("Entry deleted successfully! (Press Enter or Esc to return)".to_string());
+                        if input.confirm_password != input.new_password {
+                            screen = Screen::ErrorMessage("Passwords do not match. (Press Enter or Esc to return)".to_string());
                         } else {
-                            screen = Screen::ErrorMessage("Key not found. (Press Enter or Esc to return)".to_string());
+                            let changed = MasterKey::new(input.old_password.as_bytes(), input.key_file.as_deref()).and_then(|old| {
+                                let new = MasterKey::new(input.new_password.as_bytes(), input.key_file.as_deref())?;
+                                encrypt_decrypt::change_master_password(db, &old, &new)
+                            });
+                            screen = match changed {
+                                Ok(true) => Screen::SuccessMessage("Master password changed successfully! (Press Enter or Esc to return)".to_string()),
+                                Ok(false) => Screen::ErrorMessage("Wrong master password. (Press Enter or Esc to return)".to_string()),
+                                Err(e) => Screen::ErrorMessage(format!("{}. (Press Enter or Esc to return)", e)),
+                            };
                         }
+                        input.clear_password_change();
                     },
                     _ => {}
                 },
                 Screen::SuccessMessage(_) | Screen::ErrorMessage(_) => match key.code {
+                    // Messages shown during first setup return to the key file
+                    // prompt, and those shown during recovery to the login screen.
+                    KeyCode::Enter | KeyCode::Esc if input.vault_key.is_none() && !encrypt_decrypt::is_master_password_configured(db)? => {
+                        screen = if global.keyfile.is_some() { Screen::FirstSetup } else { Screen::FirstSetupKeyFile };
+                    },
+                    KeyCode::Enter | KeyCode::Esc if input.vault_key.is_none() => screen = Screen::Login,
                     KeyCode::Enter | KeyCode::Esc => screen = Screen::Menu,
                     _ => {}
                 },
@@ -308,7 +1515,5 @@ fn main() -> Result<(), anyhow::Error> {
         }
     }
 
-    disable_raw_mode()?;
-    execute!(stdout(), LeaveAlternateScreen)?;
-    Ok(())
+    Ok(TuiExit::Quit)
 }
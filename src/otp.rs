@@ -0,0 +1,330 @@
+//! One-time codes for two-factor authentication: HOTP (RFC 4226) and TOTP
+//! (RFC 6238). An entry's seed is stored as an `otpauth://` URI, the format
+//! sites encode in the QR code shown when 2FA is enabled, or as a bare base32
+//! secret, which is read as a TOTP seed with the usual 6 digits, 30 second
+//! period and SHA-1.
+
+use std::fmt;
+
+use base32::Alphabet;
+use hmac::{Hmac, Mac};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+use zeroize::Zeroizing;
+
+use crate::error::Error;
+use crate::recovery;
+use crate::secret::{SecretBytes, SecretString};
+
+pub const DEFAULT_DIGITS: u32 = 6;
+pub const DEFAULT_PERIOD: u64 = 30;
+
+const URI_SCHEME: &str = "otpauth://";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Algorithm {
+    #[default]
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl Algorithm {
+    pub fn name(self) -> &'static str {
+        match self {
+            Algorithm::Sha1 => "SHA1",
+            Algorithm::Sha256 => "SHA256",
+            Algorithm::Sha512 => "SHA512",
+        }
+    }
+
+    fn mac(self, key: &[u8], message: &[u8]) -> Zeroizing<Vec<u8>> {
+        fn compute<M: Mac + hmac::digest::KeyInit>(key: &[u8], message: &[u8]) -> Zeroizing<Vec<u8>> {
+            let mut mac = <M as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length");
+            mac.update(message);
+            Zeroizing::new(mac.finalize().into_bytes().to_vec())
+        }
+        match self {
+            Algorithm::Sha1 => compute::<Hmac<Sha1>>(key, message),
+            Algorithm::Sha256 => compute::<Hmac<Sha256>>(key, message),
+            Algorithm::Sha512 => compute::<Hmac<Sha512>>(key, message),
+        }
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Whether codes follow the time or a counter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    /// A new code every `period` seconds.
+    Totp { period: u64 },
+    /// A new code each time the counter is advanced, after a code is used.
+    Hotp { counter: u64 },
+}
+
+/// A parsed one-time password seed.
+#[derive(Clone, Debug)]
+pub struct Otp {
+    secret: SecretBytes,
+    pub kind: Kind,
+    pub algorithm: Algorithm,
+    pub digits: u32,
+    pub issuer: Option<String>,
+    pub account: Option<String>,
+}
+
+impl Otp {
+    /// Parses an `otpauth://totp/...` or `otpauth://hotp/...` URI, or a bare
+    /// base32 secret in which case, spaces, dashes and padding are ignored.
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let text = text.trim();
+        let Some(rest) = strip_prefix_ignore_case(text, URI_SCHEME) else {
+            return Ok(Otp {
+                secret: decode_secret(text)?,
+                kind: Kind::Totp { period: DEFAULT_PERIOD },
+                algorithm: Algorithm::Sha1,
+                digits: DEFAULT_DIGITS,
+                issuer: None,
+                account: None,
+            });
+        };
+
+        let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let (kind, label) = path.split_once('/').unwrap_or((path, ""));
+        let label = decode_component(label)?;
+        let (mut issuer, account) = match label.split_once(':') {
+            Some((issuer, account)) => (Some(issuer.trim().to_string()), account.trim().to_string()),
+            None => (None, label.trim().to_string()),
+        };
+
+        let mut secret = None;
+        let mut algorithm = Algorithm::Sha1;
+        let mut digits = DEFAULT_DIGITS;
+        let mut period = DEFAULT_PERIOD;
+        let mut counter = None;
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            match key.to_ascii_lowercase().as_str() {
+                "secret" => secret = Some(decode_secret(&decode_component(value)?)?),
+                "issuer" => issuer = Some(decode_component(value)?),
+                "algorithm" => {
+                    algorithm = match value.to_ascii_uppercase().as_str() {
+                        "SHA1" => Algorithm::Sha1,
+                        "SHA256" => Algorithm::Sha256,
+                        "SHA512" => Algorithm::Sha512,
+                        _ => return Err(Error::InvalidInput(format!("unsupported OTP algorithm {}", value))),
+                    }
+                }
+                "digits" => {
+                    digits = value.parse().ok().filter(|digits| (6..=8).contains(digits)).ok_or_else(|| {
+                        Error::InvalidInput(format!("OTP digits must be 6 to 8, not {}", value))
+                    })?
+                }
+                "period" => {
+                    period = value.parse().ok().filter(|&period| period > 0).ok_or_else(|| {
+                        Error::InvalidInput(format!("OTP period must be a positive number of seconds, not {}", value))
+                    })?
+                }
+                "counter" => {
+                    counter = Some(value.parse().map_err(|_| {
+                        Error::InvalidInput(format!("OTP counter must be a number, not {}", value))
+                    })?)
+                }
+                // Such as `image`, which some sites add for a logo.
+                _ => {}
+            }
+        }
+
+        let kind = match kind.to_ascii_lowercase().as_str() {
+            "totp" => Kind::Totp { period },
+            "hotp" => Kind::Hotp {
+                counter: counter.ok_or_else(|| Error::InvalidInput("HOTP URI has no counter".to_string()))?,
+            },
+            _ => return Err(Error::InvalidInput(format!("unknown OTP type {}, expected totp or hotp", kind))),
+        };
+        Ok(Otp {
+            secret: secret.ok_or_else(|| Error::InvalidInput("OTP URI has no secret".to_string()))?,
+            kind,
+            algorithm,
+            digits,
+            issuer: issuer.filter(|issuer| !issuer.is_empty()),
+            account: Some(account).filter(|account| !account.is_empty()),
+        })
+    }
+
+    /// The counter, or time step, that codes are generated for at `now`.
+    fn counter(&self, now: u64) -> u64 {
+        match self.kind {
+            Kind::Totp { period } => now / period,
+            Kind::Hotp { counter } => counter,
+        }
+    }
+
+    /// The code at `now`, in seconds since the Unix epoch, zero padded to
+    /// `digits`. HOTP codes do not depend on the time.
+    pub fn code(&self, now: u64) -> SecretString {
+        let code = hotp(&self.secret, self.algorithm, self.counter(now), self.digits);
+        SecretString::from(format!("{:0width$}", code, width = self.digits as usize).as_str())
+    }
+
+    /// Seconds the TOTP code at `now` stays valid, or `None` for HOTP.
+    pub fn remaining(&self, now: u64) -> Option<u64> {
+        match self.kind {
+            Kind::Totp { period } => Some(period - now % period),
+            Kind::Hotp { .. } => None,
+        }
+    }
+
+    /// Moves an HOTP seed on to the next code, once the current one is used.
+    pub fn advance(&mut self) {
+        if let Kind::Hotp { counter } = &mut self.kind {
+            *counter += 1;
+        }
+    }
+
+    /// Formats the seed as an `otpauth://` URI, to store it back.
+    pub fn to_uri(&self) -> SecretString {
+        let encode = |text: &str| utf8_percent_encode(text, NON_ALPHANUMERIC).to_string();
+        let secret = Zeroizing::new(base32::encode(Alphabet::Rfc4648 { padding: false }, &self.secret));
+        let (kind, param) = match self.kind {
+            Kind::Totp { period } => ("totp", format!("period={}", period)),
+            Kind::Hotp { counter } => ("hotp", format!("counter={}", counter)),
+        };
+        let label = match (&self.issuer, &self.account) {
+            (Some(issuer), Some(account)) => format!("{}:{}", encode(issuer), encode(account)),
+            (Some(name), None) | (None, Some(name)) => encode(name),
+            (None, None) => String::new(),
+        };
+        let mut uri = SecretString::new();
+        let parts = [URI_SCHEME, kind, "/", &label, "?secret=", &secret];
+        parts.iter().flat_map(|part| part.chars()).for_each(|c| uri.push(c));
+        if let Some(issuer) = &self.issuer {
+            format!("&issuer={}", encode(issuer)).chars().for_each(|c| uri.push(c));
+        }
+        let params = format!("&algorithm={}&digits={}&{}", self.algorithm, self.digits, param);
+        params.chars().for_each(|c| uri.push(c));
+        uri
+    }
+}
+
+/// The HOTP value of `secret` for `counter`: the HMAC of the counter,
+/// dynamically truncated to 31 bits and reduced to `digits` decimal digits.
+pub fn hotp(secret: &[u8], algorithm: Algorithm, counter: u64, digits: u32) -> u32 {
+    let mac = algorithm.mac(secret, &counter.to_be_bytes());
+    let offset = usize::from(mac[mac.len() - 1] & 0x0f);
+    let value = u32::from_be_bytes([mac[offset], mac[offset + 1], mac[offset + 2], mac[offset + 3]]) & 0x7fff_ffff;
+    value % 10u32.pow(digits)
+}
+
+fn strip_prefix_ignore_case<'a>(text: &'a str, prefix: &str) -> Option<&'a str> {
+    let head = text.get(..prefix.len())?;
+    head.eq_ignore_ascii_case(prefix).then(|| &text[prefix.len()..])
+}
+
+fn decode_component(text: &str) -> Result<String, Error> {
+    percent_decode_str(text)
+        .decode_utf8()
+        .map(|text| text.into_owned())
+        .map_err(|_| Error::InvalidInput("OTP URI is not valid UTF-8".to_string()))
+}
+
+fn decode_secret(text: &str) -> Result<SecretBytes, Error> {
+    let secret = recovery::decode_groups(text.trim_end_matches('='))
+        .ok_or_else(|| Error::InvalidInput("OTP secret is not valid base32".to_string()))?;
+    if secret.is_empty() {
+        return Err(Error::InvalidInput("OTP secret is empty".to_string()));
+    }
+    Ok(secret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEED_SHA1: &[u8] = b"12345678901234567890";
+    const SEED_SHA256: &[u8] = b"12345678901234567890123456789012";
+    const SEED_SHA512: &[u8] = b"1234567890123456789012345678901234567890123456789012345678901234";
+
+    fn uri(seed: &[u8], algorithm: Algorithm) -> String {
+        let secret = base32::encode(Alphabet::Rfc4648 { padding: true }, seed);
+        format!("otpauth://totp/Example:alice@example.com?secret={}&issuer=Example&algorithm={}&digits=8", secret, algorithm)
+    }
+
+    #[test]
+    fn test_rfc4226_vectors() {
+        let expected = [755224, 287082, 359152, 969429, 338314, 254676, 287922, 162583, 399871, 520489];
+        for (counter, code) in expected.into_iter().enumerate() {
+            assert_eq!(hotp(SEED_SHA1, Algorithm::Sha1, counter as u64, 6), code);
+        }
+
+        let secret = base32::encode(Alphabet::Rfc4648 { padding: false }, SEED_SHA1);
+        let mut otp = Otp::parse(&format!("otpauth://hotp/Bank?secret={}&counter=8", secret)).expect("valid URI");
+        assert_eq!(otp.code(0).as_str(), "399871");
+        assert_eq!(otp.remaining(0), None);
+        otp.advance();
+        assert_eq!(otp.code(1_000_000).as_str(), "520489");
+        let stored = Otp::parse(otp.to_uri().as_str()).expect("round trips");
+        assert_eq!(stored.kind, Kind::Hotp { counter: 9 });
+        assert_eq!(stored.account.as_deref(), Some("Bank"));
+    }
+
+    #[test]
+    fn test_rfc6238_vectors() {
+        // Time, then the SHA-1, SHA-256 and SHA-512 codes from RFC 6238 appendix B.
+        let vectors: [(u64, [&str; 3]); 6] = [
+            (59, ["94287082", "46119246", "90693936"]),
+            (1_111_111_109, ["07081804", "68084774", "25091201"]),
+            (1_111_111_111, ["14050471", "67062674", "99943326"]),
+            (1_234_567_890, ["89005924", "91819424", "93441116"]),
+            (2_000_000_000, ["69279037", "90698825", "38618901"]),
+            (20_000_000_000, ["65353130", "77737706", "47863826"]),
+        ];
+        let seeds = [(SEED_SHA1, Algorithm::Sha1), (SEED_SHA256, Algorithm::Sha256), (SEED_SHA512, Algorithm::Sha512)];
+        for (i, (seed, algorithm)) in seeds.into_iter().enumerate() {
+            let otp = Otp::parse(&uri(seed, algorithm)).expect("valid URI");
+            assert_eq!(otp.algorithm, algorithm);
+            for (time, codes) in vectors {
+                assert_eq!(otp.code(time).as_str(), codes[i], "{} at {}", algorithm, time);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let otp = Otp::parse(&uri(SEED_SHA1, Algorithm::Sha1)).expect("valid URI");
+        assert_eq!((otp.issuer.as_deref(), otp.account.as_deref()), (Some("Example"), Some("alice@example.com")));
+        assert_eq!((otp.kind, otp.digits), (Kind::Totp { period: 30 }, 8));
+        assert_eq!(otp.remaining(59), Some(1));
+        assert_eq!(otp.remaining(60), Some(30));
+
+        // A bare seed, as sites show under "enter this code instead".
+        let bare = Otp::parse("gezd gnbv gy3t qojq gezd gnbv gy3t qojq").expect("valid seed");
+        assert_eq!((bare.kind, bare.digits, bare.algorithm), (Kind::Totp { period: 30 }, 6, Algorithm::Sha1));
+        assert_eq!(bare.code(59).as_str(), "287082");
+
+        let label = Otp::parse("OTPAUTH://TOTP/ACME%20Co:john%40example.com?secret=GEZDGNBV&period=60&image=x").expect("valid URI");
+        assert_eq!((label.issuer.as_deref(), label.account.as_deref()), (Some("ACME Co"), Some("john@example.com")));
+        assert_eq!(label.kind, Kind::Totp { period: 60 });
+        let stored = Otp::parse(label.to_uri().as_str()).expect("round trips");
+        assert_eq!((stored.issuer, stored.account, stored.kind), (label.issuer, label.account, label.kind));
+
+        for invalid in [
+            "",
+            "not base32!",
+            "otpauth://totp/x",
+            "otpauth://hotp/x?secret=GEZDGNBV",
+            "otpauth://motp/x?secret=GEZDGNBV",
+            "otpauth://totp/x?secret=GEZDGNBV&digits=12",
+            "otpauth://totp/x?secret=GEZDGNBV&period=0",
+            "otpauth://totp/x?secret=GEZDGNBV&algorithm=MD5",
+        ] {
+            assert!(matches!(Otp::parse(invalid), Err(Error::InvalidInput(_))), "{}", invalid);
+        }
+    }
+}
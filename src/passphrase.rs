@@ -0,0 +1,195 @@
+//! Diceware style passphrases: words drawn at random from a bundled list,
+//! which are easier to remember and type than random characters, such as
+//! for the master password.
+//!
+//! The list is the EFF large wordlist (CC BY 3.0), 7776 words of 3 to 9
+//! letters, one for every roll of five dice, so every word is worth
+//! log2(7776), about 12.9 bits.
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::Error;
+use crate::generator::random_index;
+use crate::secret::SecretString;
+
+const WORDLIST: &str = include_str!("../wordlists/eff_large_wordlist.txt");
+
+pub const MIN_WORDS: usize = 3;
+pub const MAX_WORDS: usize = 64;
+
+fn words() -> impl Iterator<Item = &'static str> {
+    WORDLIST.lines()
+}
+
+/// How the words of a passphrase are capitalized.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Capitalize {
+    /// All lowercase
+    #[default]
+    None,
+    /// Only the first word
+    First,
+    /// Every word
+    Each,
+    /// Each word by a coin flip, which adds one bit per word
+    Random,
+}
+
+impl Capitalize {
+    pub const ALL: [Capitalize; 4] = [Capitalize::None, Capitalize::First, Capitalize::Each, Capitalize::Random];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Capitalize::None => "none",
+            Capitalize::First => "first",
+            Capitalize::Each => "each",
+            Capitalize::Random => "random",
+        }
+    }
+}
+
+impl fmt::Display for Capitalize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Capitalize {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Capitalize::ALL
+            .into_iter()
+            .find(|capitalize| capitalize.name().eq_ignore_ascii_case(name))
+            .ok_or_else(|| Error::InvalidInput(format!("unknown capitalization {}, expected none, first, each or random", name)))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PassphrasePolicy {
+    pub words: usize,
+    pub separator: String,
+    pub capitalize: Capitalize,
+    /// Number of words that get a random digit appended.
+    pub digits: usize,
+}
+
+impl Default for PassphrasePolicy {
+    fn default() -> Self {
+        PassphrasePolicy { words: 6, separator: "-".to_string(), capitalize: Capitalize::None, digits: 0 }
+    }
+}
+
+impl PassphrasePolicy {
+    pub fn validate(&self) -> Result<(), Error> {
+        if !(MIN_WORDS..=MAX_WORDS).contains(&self.words) {
+            return Err(Error::InvalidInput(format!("passphrases must have {} to {} words", MIN_WORDS, MAX_WORDS)));
+        }
+        if self.digits > self.words {
+            return Err(Error::InvalidInput(format!("cannot add digits to {} of {} words", self.digits, self.words)));
+        }
+        Ok(())
+    }
+
+    /// Entropy in bits of a passphrase generated with this policy, assuming
+    /// the attacker knows the policy and the wordlist.
+    pub fn entropy(&self) -> f64 {
+        let per_word = (words().count() as f64).log2();
+        let mut bits = self.words as f64 * per_word;
+        if self.capitalize == Capitalize::Random {
+            bits += self.words as f64;
+        }
+        // Which words get a digit, then the digits themselves.
+        bits += log2_binomial(self.words, self.digits) + self.digits as f64 * 10f64.log2();
+        bits
+    }
+}
+
+fn log2_binomial(n: usize, k: usize) -> f64 {
+    (0..k).map(|i| ((n - i) as f64 / (i + 1) as f64).log2()).sum()
+}
+
+/// Generates a passphrase following `policy`.
+pub fn generate(policy: &PassphrasePolicy) -> Result<SecretString, Error> {
+    policy.validate()?;
+    let list: Vec<&str> = words().collect();
+
+    // Pick which words get a digit by shuffling the first `digits` positions.
+    let mut positions: Vec<usize> = (0..policy.words).collect();
+    for i in 0..policy.digits {
+        positions.swap(i, i + random_index(policy.words - i)?);
+    }
+    let with_digit = &positions[..policy.digits];
+
+    let mut passphrase = SecretString::new();
+    for i in 0..policy.words {
+        if i > 0 {
+            policy.separator.chars().for_each(|c| passphrase.push(c));
+        }
+        let upper = match policy.capitalize {
+            Capitalize::None => false,
+            Capitalize::First => i == 0,
+            Capitalize::Each => true,
+            Capitalize::Random => random_index(2)? == 1,
+        };
+        for (j, c) in list[random_index(list.len())?].chars().enumerate() {
+            passphrase.push(if upper && j == 0 { c.to_ascii_uppercase() } else { c });
+        }
+        if with_digit.contains(&i) {
+            passphrase.push(char::from(b'0' + random_index(10)? as u8));
+        }
+    }
+    Ok(passphrase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wordlist() {
+        let list: Vec<&str> = words().collect();
+        assert_eq!(list.len(), 6usize.pow(5));
+        assert!(list.iter().all(|word| (3..=9).contains(&word.len()) && word.bytes().all(|b| b.is_ascii_lowercase() || b == b'-')));
+        assert!(list.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn test_generate_passphrase() -> Result<(), Error> {
+        let policy = PassphrasePolicy { words: 5, separator: " ".to_string(), capitalize: Capitalize::Each, digits: 2 };
+        for _ in 0..20 {
+            let passphrase = generate(&policy)?;
+            let parts: Vec<&str> = passphrase.as_str().split(' ').collect();
+            assert_eq!(parts.len(), 5);
+            assert!(parts.iter().all(|part| part.starts_with(|c: char| c.is_ascii_uppercase())));
+            assert_eq!(parts.iter().filter(|part| part.ends_with(|c: char| c.is_ascii_digit())).count(), 2);
+            for part in parts {
+                let word = part.trim_end_matches(|c: char| c.is_ascii_digit()).to_ascii_lowercase();
+                assert!(words().any(|w| w == word), "{} is not in the list", word);
+            }
+        }
+
+        let plain = generate(&PassphrasePolicy::default())?;
+        // A few words such as yo-yo contain the separator themselves.
+        assert!(plain.as_str().split('-').count() >= 6);
+        assert!(plain.as_str().bytes().all(|b| b.is_ascii_lowercase() || b == b'-'));
+
+        assert!(matches!(generate(&PassphrasePolicy { words: 2, ..PassphrasePolicy::default() }), Err(Error::InvalidInput(_))));
+        assert!(matches!(generate(&PassphrasePolicy { digits: 7, ..PassphrasePolicy::default() }), Err(Error::InvalidInput(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_entropy() {
+        let per_word = 7776f64.log2();
+        assert!((PassphrasePolicy::default().entropy() - 6.0 * per_word).abs() < 1e-9);
+        assert!((PassphrasePolicy::default().entropy() - 77.55).abs() < 0.01);
+        let random = PassphrasePolicy { capitalize: Capitalize::Random, ..PassphrasePolicy::default() };
+        assert!((random.entropy() - (6.0 * per_word + 6.0)).abs() < 1e-9);
+        // 4 words get a digit in one of 4 ways, each digit 10 ways.
+        let digits = PassphrasePolicy { words: 4, digits: 1, ..PassphrasePolicy::default() };
+        assert!((digits.entropy() - (4.0 * per_word + 2.0 + 10f64.log2())).abs() < 1e-9);
+        assert_eq!("Random".parse::<Capitalize>().ok(), Some(Capitalize::Random));
+    }
+}
@@ -0,0 +1,173 @@
+//! Printable recovery key. A random secret, shown once when it is generated,
+//! that wraps the vault key independently of the master password so a vault
+//! can still be opened, and given a new master password, if the password or
+//! key file is lost.
+
+use base32::Alphabet;
+use rand::{rngs::OsRng, TryRngCore};
+use sled::Db;
+use zeroize::Zeroizing;
+
+use crate::encrypt_decrypt::{self, KdfParams, MasterKey, VaultKey};
+use crate::error::Error;
+use crate::secret::{SecretBytes, SecretString};
+use crate::sleddb;
+
+/// 160 bits of randomness, which is 32 base32 characters.
+const RECOVERY_KEY_LEN: usize = 20;
+const GROUP_LEN: usize = 4;
+const ALPHABET: Alphabet = Alphabet::Rfc4648 { padding: false };
+
+pub struct RecoveryKey(SecretBytes);
+
+impl RecoveryKey {
+    pub fn generate() -> Result<Self, Error> {
+        let mut key = SecretBytes::zeroed(RECOVERY_KEY_LEN);
+        OsRng.try_fill_bytes(key.as_mut_slice())?;
+        Ok(RecoveryKey(key))
+    }
+
+    /// Parses a recovery key as printed by [`RecoveryKey::display`]. Case,
+    /// dashes and whitespace are ignored.
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let bytes = decode_groups(text)
+            .ok_or_else(|| Error::InvalidInput("recovery key is not valid base32".to_string()))?;
+        if bytes.len() != RECOVERY_KEY_LEN {
+            return Err(Error::InvalidInput("recovery key has the wrong length".to_string()));
+        }
+        Ok(RecoveryKey(bytes))
+    }
+
+    /// Formats the key as dash-separated groups of four base32 characters.
+    pub fn display(&self) -> SecretString {
+        encode_groups(&self.0)
+    }
+}
+
+/// Encodes bytes as base32 in dash-separated groups of four characters.
+/// Uppercase base32 and dashes fit the QR code alphanumeric mode.
+pub(crate) fn encode_groups(bytes: &[u8]) -> SecretString {
+    let encoded = Zeroizing::new(base32::encode(ALPHABET, bytes));
+    let mut display = SecretString::new();
+    for (i, c) in encoded.chars().enumerate() {
+        if i > 0 && i % GROUP_LEN == 0 {
+            display.push('-');
+        }
+        display.push(c);
+    }
+    display
+}
+
+/// Decodes text produced by [`encode_groups`], ignoring case, dashes and
+/// whitespace. Returns `None` if it is not valid base32.
+pub(crate) fn decode_groups(text: &str) -> Option<SecretBytes> {
+    let cleaned: Zeroizing<String> = Zeroizing::new(
+        text.chars()
+            .filter(|c| !c.is_whitespace() && *c != '-')
+            .map(|c| c.to_ascii_uppercase())
+            .collect(),
+    );
+    base32::decode(ALPHABET, &cleaned).map(SecretBytes::new)
+}
+
+/// Encrypts the vault key under the recovery key.
+fn wrap_vault_key(vault_key: &VaultKey, recovery: &RecoveryKey, params: &KdfParams) -> Result<Vec<u8>, Error> {
+    encrypt_decrypt::encrypt(vault_key.as_bytes(), &recovery.0, params, vault_key.cipher())
+}
+
+/// Decrypts a wrapped vault key, returning `None` if the recovery key is wrong.
+fn unwrap_vault_key(db: &Db, wrapped: &[u8], recovery: &RecoveryKey) -> Result<Option<VaultKey>, Error> {
+    match encrypt_decrypt::decrypt(wrapped, &recovery.0) {
+        Ok(key) => Ok(Some(VaultKey::from_slice(&key, encrypt_decrypt::vault_cipher(db)?)?)),
+        Err(Error::Authentication) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Generates a new recovery key for the vault, replacing any previous one.
+pub fn regenerate_recovery_key(db: &Db, vault_key: &VaultKey) -> Result<RecoveryKey, Error> {
+    let recovery = RecoveryKey::generate()?;
+    let wrapped = wrap_vault_key(vault_key, &recovery, &encrypt_decrypt::vault_kdf_params(db)?)?;
+    sleddb::insert(db, sleddb::RECOVERY_KEY, &wrapped)?;
+    Ok(recovery)
+}
+
+/// Removes the recovery key, so only the master password can unlock the vault.
+pub fn revoke_recovery_key(db: &Db) -> Result<(), Error> {
+    sleddb::remove(db, sleddb::RECOVERY_KEY)
+}
+
+pub fn has_recovery_key(db: &Db) -> Result<bool, Error> {
+    Ok(sleddb::get(db, sleddb::RECOVERY_KEY)?.is_some())
+}
+
+/// Unwraps the vault key with the recovery key and sets `new` as the master
+/// password. The recovery key stays valid. Returns `None` if the recovery
+/// key is wrong.
+pub fn recover_vault(db: &Db, recovery: &RecoveryKey, new: &MasterKey) -> Result<Option<VaultKey>, Error> {
+    let wrapped = sleddb::get(db, sleddb::RECOVERY_KEY)?
+        .ok_or_else(|| Error::Corrupted("vault has no recovery key".to_string()))?;
+    let Some(vault_key) = unwrap_vault_key(db, &wrapped, recovery)? else {
+        return Ok(None);
+    };
+    encrypt_decrypt::reset_master_password(db, &vault_key, new)?;
+    Ok(Some(vault_key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encrypt_decrypt::Cipher;
+
+    #[test]
+    fn test_recovery_key_display_round_trip() -> Result<(), Error> {
+        let recovery = RecoveryKey::generate()?;
+        let display = recovery.display();
+        assert_eq!(display.as_str().len(), 39);
+        assert_eq!(display.as_str().split('-').count(), 8);
+
+        let parsed = RecoveryKey::parse(&display.as_str().to_lowercase().replace('-', " "))?;
+        assert_eq!(&parsed.0[..], &recovery.0[..]);
+
+        assert!(matches!(RecoveryKey::parse("not base32!"), Err(Error::InvalidInput(_))));
+        assert!(matches!(RecoveryKey::parse("ABCD-EFGH"), Err(Error::InvalidInput(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_recovery_key_wraps_vault_key() -> Result<(), Error> {
+        let db = &sleddb::temporary();
+        let params = KdfParams::new(8 * 1024, 1, 1)?;
+        let vault_key = VaultKey::generate(Cipher::XChaCha20Poly1305)?;
+        let recovery = RecoveryKey::generate()?;
+        let wrapped = wrap_vault_key(&vault_key, &recovery, &params)?;
+
+        let unwrapped = unwrap_vault_key(db, &wrapped, &recovery)?.expect("recovery key should unwrap");
+        assert_eq!(unwrapped.as_bytes(), vault_key.as_bytes());
+        assert!(unwrap_vault_key(db, &wrapped, &RecoveryKey::generate()?)?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_recovery_key_recovers_vault() -> Result<(), Error> {
+        let db = &sleddb::temporary();
+        let key_file = std::env::temp_dir().join(format!("rustpass-test-recovery-{}.key", std::process::id()));
+        std::fs::write(&key_file, b"key file contents")?;
+        let with_key_file = &MasterKey::new(b"Bloromo", Some(&key_file))?;
+        let vault_key = encrypt_decrypt::store_master_password(db, with_key_file, &KdfParams::new(8 * 1024, 1, 1)?, Cipher::default())?;
+        std::fs::remove_file(&key_file)?;
+
+        // The recovery key opens the vault and drops the lost key file.
+        let recovery = regenerate_recovery_key(db, &vault_key)?;
+        let new = &MasterKey::new(b"Floroma", None)?;
+        assert!(recover_vault(db, &RecoveryKey::generate()?, new)?.is_none());
+        let recovered = recover_vault(db, &recovery, new)?.expect("recovery key unlocks");
+        assert_eq!(recovered.as_bytes(), vault_key.as_bytes());
+        assert!(!encrypt_decrypt::requires_key_file(db)?);
+        assert!(encrypt_decrypt::unlock(db, new)?.is_some());
+
+        revoke_recovery_key(db)?;
+        assert!(!has_recovery_key(db)?);
+        Ok(())
+    }
+}
@@ -0,0 +1,222 @@
+//! Containers for secrets that are wiped from memory when dropped. With the
+//! `mlock` feature on Linux their heap buffers are also locked into RAM so
+//! they cannot be swapped to disk. Locking is best effort: if the memlock
+//! limit is reached the buffer is still zeroed on drop. Pages are never
+//! unlocked, since a page can be shared with other live secrets; they stay
+//! locked until the process exits.
+
+use std::fmt;
+use std::ops::Deref;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use zeroize::Zeroize;
+
+#[cfg(all(target_os = "linux", feature = "mlock"))]
+fn lock(ptr: *const u8, len: usize) {
+    if len > 0 {
+        // SAFETY: mlock only changes paging of the given range, which is a
+        // live allocation owned by the caller.
+        unsafe { libc::mlock(ptr as *const libc::c_void, len) };
+    }
+}
+
+#[cfg(not(all(target_os = "linux", feature = "mlock")))]
+fn lock(_ptr: *const u8, _len: usize) {}
+
+/// Locks the heap allocation of `buf`, returning it unchanged.
+fn locked(buf: Vec<u8>) -> Vec<u8> {
+    lock(buf.as_ptr(), buf.capacity());
+    buf
+}
+
+/// Zeroes the whole allocation of `buf`, including spare capacity.
+fn wipe(buf: &mut Vec<u8>) {
+    buf.zeroize();
+}
+
+/// Decrypted bytes, such as an entry value or a vault key.
+pub struct SecretBytes(Vec<u8>);
+
+impl SecretBytes {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        SecretBytes(locked(bytes))
+    }
+
+    pub fn zeroed(len: usize) -> Self {
+        SecretBytes::new(vec![0u8; len])
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+impl Deref for SecretBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Clone for SecretBytes {
+    fn clone(&self) -> Self {
+        SecretBytes::new(self.0.clone())
+    }
+}
+
+impl Drop for SecretBytes {
+    fn drop(&mut self) {
+        wipe(&mut self.0);
+    }
+}
+
+impl fmt::Debug for SecretBytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretBytes([REDACTED; {}])", self.0.len())
+    }
+}
+
+/// Text input holding a secret, such as a password being typed. Growing the
+/// buffer moves the contents into a new allocation and wipes the old one, so
+/// no stale copies are left behind.
+pub struct SecretString(Vec<u8>);
+
+impl SecretString {
+    const INITIAL_CAPACITY: usize = 64;
+
+    pub fn new() -> Self {
+        SecretString(locked(Vec::with_capacity(Self::INITIAL_CAPACITY)))
+    }
+
+    pub fn push(&mut self, c: char) {
+        let mut encoded = [0u8; 4];
+        let encoded = c.encode_utf8(&mut encoded).as_bytes();
+        if self.0.len() + encoded.len() > self.0.capacity() {
+            let mut grown = locked(Vec::with_capacity((self.0.capacity() * 2).max(Self::INITIAL_CAPACITY)));
+            grown.extend_from_slice(&self.0);
+            wipe(&mut self.0);
+            self.0 = grown;
+        }
+        self.0.extend_from_slice(encoded);
+    }
+
+    pub fn pop(&mut self) -> Option<char> {
+        let c = self.as_str().chars().next_back()?;
+        let start = self.0.len() - c.len_utf8();
+        self.0[start..].zeroize();
+        self.0.truncate(start);
+        Some(c)
+    }
+
+    pub fn clear(&mut self) {
+        self.0[..].zeroize();
+        self.0.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn as_str(&self) -> &str {
+        std::str::from_utf8(&self.0).expect("SecretString only holds whole chars")
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Default for SecretString {
+    fn default() -> Self {
+        SecretString::new()
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(s: &str) -> Self {
+        let mut secret = SecretString::new();
+        s.chars().for_each(|c| secret.push(c));
+        secret
+    }
+}
+
+impl Clone for SecretString {
+    fn clone(&self) -> Self {
+        SecretString::from(self.as_str())
+    }
+}
+
+impl Serialize for SecretString {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+/// Deserializes directly from the input where the format allows it, so no
+/// intermediate `String` is left behind.
+impl<'de> Deserialize<'de> for SecretString {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl serde::de::Visitor<'_> for Visitor {
+            type Value = SecretString;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a string")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<SecretString, E> {
+                Ok(SecretString::from(value))
+            }
+        }
+
+        deserializer.deserialize_str(Visitor)
+    }
+}
+
+impl PartialEq for SecretString {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl Drop for SecretString {
+    fn drop(&mut self) {
+        wipe(&mut self.0);
+    }
+}
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretString([REDACTED])")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secret_string_edits() {
+        let mut secret = SecretString::new();
+        for c in "pässwörd".chars() {
+            secret.push(c);
+        }
+        assert_eq!(secret.as_str(), "pässwörd");
+        assert_eq!(secret.pop(), Some('d'));
+        assert_eq!(secret.pop(), Some('r'));
+        assert_eq!(secret.pop(), Some('ö'));
+        assert_eq!(secret.as_str(), "pässw");
+        secret.clear();
+        assert!(secret.is_empty());
+        assert_eq!(secret.pop(), None);
+    }
+
+    #[test]
+    fn test_secret_string_grows() {
+        let long = "x".repeat(SecretString::INITIAL_CAPACITY * 3 + 1);
+        let secret = SecretString::from(long.as_str());
+        assert_eq!(secret.as_str(), long);
+    }
+}
@@ -0,0 +1,290 @@
+//! Shamir secret sharing of the vault key for team escrow. The key is split
+//! into `n` shares so that any `k` of them rebuild it, while fewer reveal
+//! nothing about it. Arithmetic is over GF(2^8), one polynomial per key byte.
+//!
+//! A share is printed as `RPS-` followed by base32 groups of
+//! `[version][split id][k][x][share bytes][checksum]`. The split id stops
+//! shares from different splits being mixed, and the checksum catches typos.
+
+use rand::{rngs::OsRng, TryRngCore};
+use sha2::{Digest, Sha256};
+use sled::Db;
+
+use crate::encrypt_decrypt::{self, MasterKey, VaultKey};
+use crate::error::Error;
+use crate::recovery;
+use crate::secret::{SecretBytes, SecretString};
+use crate::sleddb;
+
+const SHARE_PREFIX: &str = "RPS-";
+const SHARE_VERSION: u8 = 1;
+const SPLIT_ID_LEN: usize = 4;
+const CHECKSUM_LEN: usize = 4;
+/// Version, split id, threshold and index.
+const SHARE_HEADER_LEN: usize = 1 + SPLIT_ID_LEN + 1 + 1;
+
+/// Plaintext sealed under the vault key to check a rebuilt key before use.
+const KEY_CHECK: &[u8] = b"rustpass key check";
+
+pub struct Share {
+    split_id: [u8; SPLIT_ID_LEN],
+    threshold: u8,
+    index: u8,
+    value: SecretBytes,
+}
+
+impl Share {
+    /// Number of shares needed to rebuild the secret.
+    pub fn threshold(&self) -> u8 {
+        self.threshold
+    }
+
+    pub fn index(&self) -> u8 {
+        self.index
+    }
+
+    pub fn encode(&self) -> SecretString {
+        let mut bytes = Vec::with_capacity(SHARE_HEADER_LEN + self.value.len() + CHECKSUM_LEN);
+        bytes.push(SHARE_VERSION);
+        bytes.extend_from_slice(&self.split_id);
+        bytes.push(self.threshold);
+        bytes.push(self.index);
+        bytes.extend_from_slice(&self.value);
+        let checksum = Sha256::digest(&bytes);
+        bytes.extend_from_slice(&checksum[..CHECKSUM_LEN]);
+        let bytes = SecretBytes::new(bytes);
+
+        let mut text = SecretString::from(SHARE_PREFIX);
+        for c in recovery::encode_groups(&bytes).as_str().chars() {
+            text.push(c);
+        }
+        text
+    }
+
+    /// Parses a share printed by [`Share::encode`]. Case, dashes and
+    /// whitespace are ignored.
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let text = text.trim();
+        let body = text
+            .get(..SHARE_PREFIX.len())
+            .filter(|prefix| prefix.eq_ignore_ascii_case(SHARE_PREFIX))
+            .map(|_| &text[SHARE_PREFIX.len()..])
+            .ok_or_else(|| Error::InvalidInput(format!("share must start with {}", SHARE_PREFIX)))?;
+        let bytes = recovery::decode_groups(body)
+            .ok_or_else(|| Error::InvalidInput("share is not valid base32".to_string()))?;
+        if bytes.len() <= SHARE_HEADER_LEN + CHECKSUM_LEN {
+            return Err(Error::InvalidInput("share is too short".to_string()));
+        }
+        let (payload, checksum) = bytes.split_at(bytes.len() - CHECKSUM_LEN);
+        if Sha256::digest(payload)[..CHECKSUM_LEN] != *checksum {
+            return Err(Error::InvalidInput("share checksum does not match, check for typos".to_string()));
+        }
+        if payload[0] != SHARE_VERSION {
+            return Err(Error::UnknownFormat(format!("share version {}", payload[0])));
+        }
+
+        let mut split_id = [0u8; SPLIT_ID_LEN];
+        split_id.copy_from_slice(&payload[1..1 + SPLIT_ID_LEN]);
+        let threshold = payload[1 + SPLIT_ID_LEN];
+        let index = payload[2 + SPLIT_ID_LEN];
+        if index == 0 || threshold == 0 {
+            return Err(Error::InvalidInput("share has an invalid index or threshold".to_string()));
+        }
+        Ok(Share { split_id, threshold, index, value: SecretBytes::new(payload[SHARE_HEADER_LEN..].to_vec()) })
+    }
+}
+
+/// Multiplication in GF(2^8) with the AES polynomial, without data
+/// dependent branches.
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0u8;
+    for _ in 0..8 {
+        product ^= a & 0u8.wrapping_sub(b & 1);
+        let carry = 0u8.wrapping_sub(a >> 7);
+        a = (a << 1) ^ (0x1b & carry);
+        b >>= 1;
+    }
+    product
+}
+
+/// Multiplicative inverse in GF(2^8), computed as `a^254`.
+fn gf_inv(a: u8) -> u8 {
+    let mut result = 1u8;
+    let mut base = a;
+    let mut exponent = 254u8;
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = gf_mul(result, base);
+        }
+        base = gf_mul(base, base);
+        exponent >>= 1;
+    }
+    result
+}
+
+/// Splits `secret` into `count` shares, any `threshold` of which rebuild it.
+pub fn split(secret: &[u8], threshold: u8, count: u8) -> Result<Vec<Share>, Error> {
+    if threshold < 2 || threshold > count {
+        return Err(Error::InvalidInput("need 2 <= threshold <= number of shares".to_string()));
+    }
+    let mut split_id = [0u8; SPLIT_ID_LEN];
+    OsRng.try_fill_bytes(&mut split_id)?;
+
+    let mut shares: Vec<Share> = (1..=count)
+        .map(|index| Share { split_id, threshold, index, value: SecretBytes::zeroed(secret.len()) })
+        .collect();
+    // coefficients[0] is the secret byte, the rest are random.
+    let mut coefficients = SecretBytes::zeroed(threshold as usize);
+    for (i, &byte) in secret.iter().enumerate() {
+        coefficients.as_mut_slice()[0] = byte;
+        OsRng.try_fill_bytes(&mut coefficients.as_mut_slice()[1..])?;
+        for share in shares.iter_mut() {
+            // Horner's rule, highest coefficient first.
+            let y = coefficients.iter().rev().fold(0u8, |y, &c| gf_mul(y, share.index) ^ c);
+            share.value.as_mut_slice()[i] = y;
+        }
+    }
+    Ok(shares)
+}
+
+/// Rebuilds the secret from at least `threshold` shares of the same split by
+/// Lagrange interpolation at zero.
+pub fn combine(shares: &[Share]) -> Result<SecretBytes, Error> {
+    let first = shares.first().ok_or_else(|| Error::InvalidInput("no shares given".to_string()))?;
+    if shares.iter().any(|s| s.split_id != first.split_id || s.threshold != first.threshold || s.value.len() != first.value.len()) {
+        return Err(Error::InvalidInput("shares come from different splits".to_string()));
+    }
+    for (i, share) in shares.iter().enumerate() {
+        if shares[..i].iter().any(|other| other.index == share.index) {
+            return Err(Error::InvalidInput(format!("share {} was given twice", share.index)));
+        }
+    }
+    if shares.len() < first.threshold as usize {
+        return Err(Error::InvalidInput(format!("{} of {} shares given", shares.len(), first.threshold)));
+    }
+
+    let shares = &shares[..first.threshold as usize];
+    let mut secret = SecretBytes::zeroed(first.value.len());
+    for share in shares {
+        // Lagrange basis polynomial for this share evaluated at x = 0.
+        let mut numerator = 1u8;
+        let mut denominator = 1u8;
+        for other in shares.iter().filter(|other| other.index != share.index) {
+            numerator = gf_mul(numerator, other.index);
+            denominator = gf_mul(denominator, other.index ^ share.index);
+        }
+        let basis = gf_mul(numerator, gf_inv(denominator));
+        for (out, &y) in secret.as_mut_slice().iter_mut().zip(share.value.iter()) {
+            *out ^= gf_mul(y, basis);
+        }
+    }
+    Ok(secret)
+}
+
+/// Splits the vault key into shares and stores a check value so a rebuilt
+/// key can be verified. Shares from earlier splits stay valid as long as the
+/// vault key is the same.
+pub fn split_vault_key(db: &Db, vault_key: &VaultKey, threshold: u8, count: u8) -> Result<Vec<Share>, Error> {
+    let shares = split(vault_key.as_bytes(), threshold, count)?;
+    let check = encrypt_decrypt::encrypt_with_key(KEY_CHECK, vault_key, &sleddb::vault_id(db)?)?;
+    sleddb::insert(db, sleddb::SHARES_CHECK, &check)?;
+    Ok(shares)
+}
+
+/// Returns true if the vault key has been split into shares.
+pub fn has_shares(db: &Db) -> Result<bool, Error> {
+    Ok(sleddb::get(db, sleddb::SHARES_CHECK)?.is_some())
+}
+
+/// Rebuilds the vault key from shares and sets `new` as the master password.
+/// Returns `None` if the shares do not rebuild this vault's key.
+pub fn recover_vault(db: &Db, shares: &[Share], new: &MasterKey) -> Result<Option<VaultKey>, Error> {
+    let check = sleddb::get(db, sleddb::SHARES_CHECK)?
+        .ok_or_else(|| Error::Corrupted("vault key has not been split into shares".to_string()))?;
+    let secret = combine(shares)?;
+    let Ok(vault_key) = VaultKey::from_slice(&secret, encrypt_decrypt::vault_cipher(db)?) else {
+        return Ok(None);
+    };
+    match encrypt_decrypt::decrypt_with_key(&check, &vault_key, &sleddb::vault_id(db)?) {
+        Ok(_) => {}
+        Err(Error::Authentication) => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    encrypt_decrypt::reset_master_password(db, &vault_key, new)?;
+    Ok(Some(vault_key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encrypt_decrypt::{Cipher, KdfParams};
+
+    #[test]
+    fn test_gf_arithmetic() {
+        assert_eq!(gf_mul(0x57, 0x83), 0xc1);
+        for a in 1..=255u8 {
+            assert_eq!(gf_mul(a, gf_inv(a)), 1);
+        }
+    }
+
+    #[test]
+    fn test_split_combine() -> Result<(), Error> {
+        let secret = b"0123456789abcdef0123456789abcdef";
+        let shares = split(secret, 3, 5)?;
+        assert_eq!(shares.len(), 5);
+
+        for picked in [[0, 1, 2], [4, 2, 0], [1, 3, 4]] {
+            let subset: Vec<Share> = picked.iter().map(|&i| Share::parse(shares[i].encode().as_str())).collect::<Result<_, _>>()?;
+            assert_eq!(&combine(&subset)?[..], secret);
+        }
+
+        let two: Vec<Share> = shares[..2].iter().map(|s| Share::parse(s.encode().as_str())).collect::<Result<_, _>>()?;
+        assert!(matches!(combine(&two), Err(Error::InvalidInput(_))));
+
+        let mut mixed: Vec<Share> = split(secret, 3, 5)?.into_iter().take(2).collect();
+        mixed.push(Share::parse(shares[2].encode().as_str())?);
+        assert!(matches!(combine(&mixed), Err(Error::InvalidInput(_))));
+
+        assert!(matches!(split(secret, 1, 5), Err(Error::InvalidInput(_))));
+        assert!(matches!(split(secret, 4, 3), Err(Error::InvalidInput(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_share_text() -> Result<(), Error> {
+        let shares = split(&[7u8; 32], 2, 3)?;
+        let text = shares[1].encode();
+        assert!(text.as_str().starts_with(SHARE_PREFIX));
+        assert!(text.as_str().chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '-'));
+
+        let parsed = Share::parse(&text.as_str().to_lowercase())?;
+        assert_eq!((parsed.threshold(), parsed.index()), (2, 2));
+
+        // Change one character of the base32 body.
+        let mut typo: Vec<char> = text.as_str().chars().collect();
+        typo[10] = if typo[10] == 'A' { 'B' } else { 'A' };
+        let typo: String = typo.into_iter().collect();
+        assert!(matches!(Share::parse(&typo), Err(Error::InvalidInput(_))));
+        assert!(matches!(Share::parse("XYZ-AAAA"), Err(Error::InvalidInput(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_shares_recover_vault() -> Result<(), Error> {
+        let db = &sleddb::temporary();
+        let password = &MasterKey::new(b"Bloromo", None)?;
+        let vault_key = encrypt_decrypt::store_master_password(db, password, &KdfParams::new(8 * 1024, 1, 1)?, Cipher::default())?;
+
+        // Any two of three key shares rebuild the vault key and set a new
+        // master password.
+        let shares = split_vault_key(db, &vault_key, 2, 3)?;
+        let wrong = split(VaultKey::generate(Cipher::default())?.as_bytes(), 2, 2)?;
+        let new = &MasterKey::new(b"Floroma", None)?;
+        assert!(recover_vault(db, &wrong, new)?.is_none());
+        let recovered = recover_vault(db, &shares[1..], new)?.expect("shares unlock");
+        assert_eq!(recovered.as_bytes(), vault_key.as_bytes());
+        assert!(encrypt_decrypt::unlock(db, new)?.is_some());
+        assert!(encrypt_decrypt::unlock(db, password)?.is_none());
+        Ok(())
+    }
+}
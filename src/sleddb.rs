@@ -1,66 +1,436 @@
-use once_cell::sync::Lazy;
-use sled::{Db, IVec};
-use crate::encrypt_decrypt;
-
-static DB: Lazy<Db> = Lazy::new(||{
-    sled::open("rustpass_db").expect("Unable to create sled db")
-});
-
-pub fn insert(key:&str, value:&Vec<u8>)-> sled::Result<()>{
-    let value:Vec<u8> = value.clone();
-    DB.insert(key, value)?;
-    DB.flush()?;
+use std::path::Path;
+
+use rand::{rngs::OsRng, TryRngCore};
+use sled::transaction::{ConflictableTransactionError, TransactionError, Transactional};
+use sled::{Batch, Db, IVec, Tree};
+use crate::encrypt_decrypt::{self, VaultKey};
+use crate::error::Error;
+use crate::secret::SecretBytes;
+
+/// Key holding the vault key wrapped under the master password.
+pub const VAULT_KEY: &str = "vault_key";
+
+/// Key holding the vault's Argon2 parameters.
+pub const KDF_PARAMS: &str = "kdf_params";
+
+/// Key holding the random vault ID bound into every entry blob.
+pub const VAULT_ID: &str = "vault_id";
+
+/// Key holding the PHC-format Argon2id verifier of the master password.
+pub const VERIFIER: &str = "verifier";
+
+/// Key holding whether the vault requires a key file to unlock.
+pub const KEY_FILE: &str = "key_file";
+
+/// Key holding the id of the cipher used for new blobs.
+pub const CIPHER: &str = "cipher";
+
+/// Key holding the vault key wrapped under the recovery key.
+pub const RECOVERY_KEY: &str = "recovery_key";
+
+/// Key holding a blob sealed under the vault key, used to check a vault key
+/// rebuilt from Shamir shares.
+pub const SHARES_CHECK: &str = "shares_check";
+
+/// Key holding the saved password generator profiles, sealed under the
+/// vault key.
+pub const GENERATOR_PROFILES: &str = "generator_profiles";
+
+/// Raw Argon2 salt and output used to verify the master password before the
+/// PHC verifier existed. Removed once a vault is migrated.
+pub const LEGACY_SALT: &str = "salt";
+pub const LEGACY_HASH: &str = "hash";
+
+/// Metadata keys that older versions stored in the default tree, next to
+/// the entries. Every other key there is an entry title, even one that
+/// matches a current metadata key.
+const LEGACY_METADATA: [&str; 2] = [LEGACY_SALT, LEGACY_HASH];
+
+/// Tree holding vault metadata, such as the verifier and wrapped vault key.
+const META_TREE: &str = "meta";
+
+/// Tree holding encrypted entries, keyed by entry ID.
+const ENTRIES_TREE: &str = "entries";
+
+/// Tree holding deleted entries, keyed by entry ID. Each value is the
+/// deletion time, in seconds since the Unix epoch as a little-endian `u64`,
+/// followed by the entry's blob, which stays bound to its ID.
+const TRASH_TREE: &str = "trash";
+
+/// Length in bytes of a random entry ID, which is stored hex encoded.
+const ENTRY_ID_LEN: usize = 16;
+
+/// An entry ID with its decrypted value, or the reason it could not be decrypted.
+pub type DecryptedEntry = (String, Result<SecretBytes, Error>);
+
+/// A deleted entry's ID, deletion time and decrypted value.
+pub type DecryptedTrash = (String, u64, Result<SecretBytes, Error>);
+
+/// Opens the vault database at `path`, creating it and its parent
+/// directories if needed.
+pub fn open(path: &Path) -> Result<Db, Error> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let db = sled::open(path)?;
+    migrate_metadata(&db)?;
+    Ok(db)
+}
+
+/// Opens an in-memory database that is deleted when dropped, for tests.
+#[cfg(test)]
+pub fn temporary() -> Db {
+    sled::Config::new().temporary(true).open().expect("unable to open temporary db")
+}
+
+fn meta(db: &Db) -> Result<Tree, Error> {
+    Ok(db.open_tree(META_TREE)?)
+}
+
+pub fn entries(db: &Db) -> Result<Tree, Error> {
+    Ok(db.open_tree(ENTRIES_TREE)?)
+}
+
+fn trash(db: &Db) -> Result<Tree, Error> {
+    Ok(db.open_tree(TRASH_TREE)?)
+}
+
+fn transaction_error(e: TransactionError<Error>) -> Error {
+    match e {
+        TransactionError::Abort(e) => e,
+        TransactionError::Storage(e) => Error::Storage(e),
+    }
+}
+
+/// Moves metadata that older versions kept in the default tree into the
+/// metadata tree, in one transaction. Entries left in the default tree are
+/// moved by [`migrate_legacy_entries`] once the vault key is known.
+fn migrate_metadata(db: &Db) -> Result<(), Error> {
+    let legacy: &Tree = db;
+    let mut found = false;
+    for key in LEGACY_METADATA {
+        found |= legacy.contains_key(key)?;
+    }
+    if !found {
+        return Ok(());
+    }
+    (legacy, &meta(db)?)
+        .transaction(|(legacy, meta)| {
+            for key in LEGACY_METADATA {
+                if let Some(value) = legacy.remove(key)? {
+                    meta.insert(key, value)?;
+                }
+            }
+            Ok(())
+        })
+        .map_err(transaction_error)?;
+    db.flush()?;
+    Ok(())
+}
+
+pub fn insert(db: &Db, key:&str, value:&[u8])-> Result<(), Error>{
+    meta(db)?.insert(key, value)?;
+    db.flush()?;
+    Ok(())
+}
+
+/// Writes all metadata records and removes all `removals` in one atomic batch.
+pub fn write_batch(db: &Db, records: &[(&str, Vec<u8>)], removals: &[&str]) -> Result<(), Error> {
+    let mut batch = Batch::default();
+    for (key, value) in records {
+        batch.insert(*key, value.as_slice());
+    }
+    for key in removals {
+        batch.remove(*key);
+    }
+    meta(db)?.apply_batch(batch)?;
+    db.flush()?;
+    Ok(())
+}
+
+pub fn remove(db: &Db, key: &str) -> Result<(), Error> {
+    meta(db)?.remove(key)?;  // Deletes the key if it exists
+    db.flush()?;       // Ensures changes are persisted
+    Ok(())
+}
+
+
+pub fn get(db: &Db, key:&str)-> Result<Option<IVec>, Error>{
+    Ok(meta(db)?.get(key)?)
+}
+
+pub fn vault_id(db: &Db) -> Result<Vec<u8>, Error> {
+    match get(db, VAULT_ID)? {
+        Some(vault_id) => Ok(vault_id.to_vec()),
+        None => Err(Error::Corrupted("missing vault id".to_string())),
+    }
+}
+
+/// Generates a random ID for a new entry.
+pub fn new_entry_id() -> Result<String, Error> {
+    let mut id = [0u8; ENTRY_ID_LEN];
+    OsRng.try_fill_bytes(&mut id)?;
+    Ok(hex::encode(id))
+}
+
+/// Encrypts `value` with the vault key, bound to the entry ID and this vault.
+pub fn seal_entry(db: &Db, vault_key: &VaultKey, id: &str, value: &[u8]) -> Result<Vec<u8>, Error> {
+    let context = encrypt_decrypt::entry_context(&vault_id(db)?, id);
+    encrypt_decrypt::encrypt_with_key(value, vault_key, &context)
+}
+
+/// Encrypts `value` with the vault key, bound to the entry ID and this vault, and stores it.
+pub fn insert_entry(db: &Db, vault_key: &VaultKey, id: &str, value: &[u8]) -> Result<(), Error> {
+    entries(db)?.insert(id, seal_entry(db, vault_key, id, value)?)?;
+    db.flush()?;
     Ok(())
 }
 
-pub fn remove(key: &str) -> sled::Result<()> {
-    DB.remove(key)?;  // Deletes the key if it exists
-    DB.flush()?;       // Ensures changes are persisted
+/// Decrypts the entry stored under `id`, or returns `None` if there is none.
+pub fn get_entry(db: &Db, vault_key: &VaultKey, id: &str) -> Result<Option<SecretBytes>, Error> {
+    let Some(value) = entries(db)?.get(id)? else {
+        return Ok(None);
+    };
+    let context = encrypt_decrypt::entry_context(&vault_id(db)?, id);
+    encrypt_decrypt::decrypt_entry(&value, vault_key, &context).map(Some)
+}
+
+pub fn remove_entry(db: &Db, id: &str) -> Result<(), Error> {
+    entries(db)?.remove(id)?;
+    db.flush()?;
     Ok(())
 }
 
+/// Moves the entry `id` to the trash, recording `deleted` as its deletion
+/// time. Returns false if there is no such entry.
+pub fn trash_entry(db: &Db, id: &str, deleted: u64) -> Result<bool, Error> {
+    let moved = (&entries(db)?, &trash(db)?)
+        .transaction(|(entries, trash)| {
+            let Some(blob) = entries.remove(id)? else {
+                return Ok(false);
+            };
+            trash.insert(id, [&deleted.to_le_bytes()[..], &blob].concat())?;
+            Ok(true)
+        })
+        .map_err(transaction_error)?;
+    db.flush()?;
+    Ok(moved)
+}
 
-pub fn get(key:&str)-> Option<IVec>{
-    match DB.get(key) {
-        Ok(n) => {
-            return n;
-        },
-        Err(e) => {
-            println!("Unable to get DB - Error:{}",e);
-            return None;
+/// Moves the entry `id` from the trash back to the entries. Returns false
+/// if it is not in the trash.
+pub fn untrash_entry(db: &Db, id: &str) -> Result<bool, Error> {
+    let moved = (&entries(db)?, &trash(db)?)
+        .transaction(|(entries, trash)| {
+            let Some(value) = trash.remove(id)? else {
+                return Ok(false);
+            };
+            let Some(blob) = value.get(8..) else {
+                return Err(ConflictableTransactionError::Abort(Error::Corrupted(format!("trashed entry {} is truncated", id))));
+            };
+            entries.insert(id, blob)?;
+            Ok(true)
+        })
+        .map_err(transaction_error)?;
+    db.flush()?;
+    Ok(moved)
+}
+
+/// Permanently deletes the entry `id` from the trash.
+pub fn purge_entry(db: &Db, id: &str) -> Result<(), Error> {
+    trash(db)?.remove(id)?;
+    db.flush()?;
+    Ok(())
+}
+
+/// Permanently deletes every trashed entry deleted before `cutoff`. Entries
+/// whose deletion time cannot be read are kept. Returns the number purged.
+pub fn purge_trash_before(db: &Db, cutoff: u64) -> Result<usize, Error> {
+    let trash = trash(db)?;
+    let mut purged = 0;
+    for result in trash.iter() {
+        let (id, value) = result?;
+        if deletion_time(&value).is_some_and(|deleted| deleted < cutoff) {
+            trash.remove(id)?;
+            purged += 1;
         }
     }
+    if purged > 0 {
+        db.flush()?;
+    }
+    Ok(purged)
+}
+
+fn deletion_time(value: &[u8]) -> Option<u64> {
+    Some(u64::from_le_bytes(value.get(..8)?.try_into().ok()?))
+}
+
+/// Decrypts every trashed entry, like [`iter_get_passwords`].
+pub fn iter_trash(db: &Db, vault_key: &VaultKey) -> Result<Vec<DecryptedTrash>, Error> {
+    let vault_id = vault_id(db)?;
+    let mut result_vec = Vec::new();
+
+    for result in trash(db)?.iter() {
+        let (id, value) = result?;
+        let id = String::from_utf8_lossy(&id).into_owned();
+        let (deleted, decrypted_value) = match (deletion_time(&value), value.get(8..)) {
+            (Some(deleted), Some(blob)) => {
+                let context = encrypt_decrypt::entry_context(&vault_id, &id);
+                (deleted, encrypt_decrypt::decrypt_entry(blob, vault_key, &context))
+            }
+            _ => (0, Err(Error::Truncated)),
+        };
+        result_vec.push((id, deleted, decrypted_value));
+    }
+
+    Ok(result_vec)
 }
 
-pub fn iter_get_passwords(masterpassword: &[u8]) -> Result<Vec<(String, Vec<u8>)>, anyhow::Error> {
-    let mut result_vec: Vec<(String, Vec<u8>)> = Vec::new();
+/// Decrypts every entry. Entries that fail to decrypt are returned with
+/// their error instead of aborting the whole listing; only storage errors
+/// fail the call.
+pub fn iter_get_passwords(db: &Db, vault_key: &VaultKey) -> Result<Vec<DecryptedEntry>, Error> {
+    let vault_id = vault_id(db)?;
+    let mut result_vec = Vec::new();
+
+    for result in entries(db)?.iter() {
+        let (id, value) = result?;
+        let id = String::from_utf8_lossy(&id).into_owned();
+        let context = encrypt_decrypt::entry_context(&vault_id, &id);
+        let decrypted_value = encrypt_decrypt::decrypt_entry(&value, vault_key, &context);
+        result_vec.push((id, decrypted_value));
+    }
+
+    Ok(result_vec)
+}
 
-    for result in DB.iter() {
+/// Rewrites every entry, trashed or not, not encrypted with the vault key's
+/// cipher, bound to its ID and `vault_id`, and stores `metadata` in the same
+/// transaction, so a failure leaves the vault untouched. Returns the number
+/// of rewritten entries.
+pub fn upgrade_entries(db: &Db, vault_key: &VaultKey, vault_id: &[u8], metadata: &[(&str, Vec<u8>)]) -> Result<usize, Error> {
+    let entries = entries(db)?;
+    let trash = trash(db)?;
+    let reseal = |id: &IVec, blob: &[u8]| -> Result<Option<Vec<u8>>, Error> {
+        if encrypt_decrypt::is_current_with(blob, vault_key.cipher()) {
+            return Ok(None);
+        }
+        let id_str = String::from_utf8_lossy(id);
+        let context = encrypt_decrypt::entry_context(vault_id, &id_str);
+        let plaintext = encrypt_decrypt::decrypt_with_key(blob, vault_key, &context)
+            .map_err(|e| Error::Corrupted(format!("entry {} cannot be decrypted ({}), delete it first", id_str, e)))?;
+        encrypt_decrypt::encrypt_with_key(&plaintext, vault_key, &context).map(Some)
+    };
+
+    let mut rewritten = Vec::new();
+    for result in entries.iter() {
+        let (id, value) = result?;
+        if let Some(blob) = reseal(&id, &value)? {
+            rewritten.push((id, blob));
+        }
+    }
+    let mut rewritten_trash = Vec::new();
+    for result in trash.iter() {
+        let (id, value) = result?;
+        let (Some(deleted), Some(blob)) = (value.get(..8), value.get(8..)) else {
+            return Err(Error::Corrupted(format!("trashed entry {} is truncated", String::from_utf8_lossy(&id))));
+        };
+        if let Some(blob) = reseal(&id, blob)? {
+            rewritten_trash.push((id, [deleted, &blob].concat()));
+        }
+    }
+
+    (&meta(db)?, &entries, &trash)
+        .transaction(|(meta, entries, trash)| {
+            for (id, value) in &rewritten {
+                entries.insert(id, value.as_slice())?;
+            }
+            for (id, value) in &rewritten_trash {
+                trash.insert(id, value.as_slice())?;
+            }
+            for (key, value) in metadata {
+                meta.insert(*key, value.as_slice())?;
+            }
+            Ok(())
+        })
+        .map_err(transaction_error)?;
+    db.flush()?;
+    Ok(rewritten.len() + rewritten_trash.len())
+}
+
+/// Moves entries that older versions stored in the default tree, keyed by
+/// title, into the entries tree under new IDs, in one transaction. `convert`
+/// gets the old key, the new ID and the stored blob, and returns the blob to
+/// store under the new ID, or `None` to leave the entry where it is. Returns
+/// the number of entries moved.
+pub fn migrate_legacy_entries(db: &Db, mut convert: impl FnMut(&str, &str, &[u8]) -> Result<Option<Vec<u8>>, Error>) -> Result<usize, Error> {
+    let legacy: &Tree = db;
+    let mut moved = Vec::new();
+    for result in legacy.iter() {
         let (key, value) = result?;
-        let key_str = String::from_utf8(key.to_vec())?;
-        if key_str == "salt" || key_str == "hash" {
-            continue;
+        let id = new_entry_id()?;
+        if let Some(sealed) = convert(&String::from_utf8_lossy(&key), &id, &value)? {
+            moved.push((key, id, sealed));
         }
-        let decrypted_value = encrypt_decrypt::decrypt(&value, masterpassword)?;
-        result_vec.push((key_str, decrypted_value));
+    }
+    if moved.is_empty() {
+        return Ok(0);
     }
 
-    Ok(result_vec)
+    (legacy, &entries(db)?)
+        .transaction(|(legacy, entries)| {
+            for (key, id, sealed) in &moved {
+                entries.insert(id.as_str(), sealed.as_slice())?;
+                legacy.remove(key)?;
+            }
+            Ok(())
+        })
+        .map_err(transaction_error)?;
+    db.flush()?;
+    Ok(moved.len())
 }
 
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::encrypt_decrypt::Cipher;
     #[test]
     fn insert_get(){
         let key = "Gmail";
         let value = b"Blueblue";
-        let value = encrypt_decrypt::encrypt(value, b"shakalaka").expect("unable to encrypt");
-        let _ = insert(key, &value);
-        if let Some(sled_value) = get(key){
+        let vault_key = VaultKey::generate(Cipher::default()).expect("unable to generate key");
+        let value = encrypt_decrypt::encrypt_with_key(value, &vault_key, key.as_bytes()).expect("unable to encrypt");
+        let db = temporary();
+        let _ = insert(&db, key, &value);
+        if let Some(sled_value) = get(&db, key).expect("unable to read db"){
             println!("Seld_value: {:?}", &sled_value);
             assert_eq!(&sled_value , &value);
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_migrate_metadata() -> Result<(), Error> {
+        let db = temporary();
+        db.insert("salt", "legacy salt")?;
+        db.insert("hash", "legacy hash")?;
+        for title in ["Gmail", CIPHER, VERIFIER, "entry_format"] {
+            db.insert(title, "entry")?;
+        }
+        migrate_metadata(&db)?;
+
+        assert_eq!(get(&db, LEGACY_SALT)?.as_deref(), Some(&b"legacy salt"[..]));
+        assert_eq!(get(&db, LEGACY_HASH)?.as_deref(), Some(&b"legacy hash"[..]));
+        assert!(get(&db, CIPHER)?.is_none() && get(&db, VERIFIER)?.is_none());
+        let legacy: Vec<IVec> = db.iter().keys().collect::<Result<_, _>>()?;
+        assert_eq!(legacy, [IVec::from("Gmail"), IVec::from(CIPHER), IVec::from("entry_format"), IVec::from(VERIFIER)]);
+
+        // An entry titled like a metadata key no longer clobbers it.
+        insert(&db, VAULT_ID, &[7; 16])?;
+        let vault_key = VaultKey::generate(Cipher::default())?;
+        insert_entry(&db, &vault_key, "hash", b"not the verifier")?;
+        assert_eq!(get(&db, LEGACY_HASH)?.as_deref(), Some(&b"legacy hash"[..]));
+        assert_eq!(&get_entry(&db, &vault_key, "hash")?.expect("stored")[..], b"not the verifier");
+        Ok(())
+    }
+}
@@ -0,0 +1,553 @@
+//! Password strength estimation in the style of zxcvbn. The password is
+//! broken into the patterns an attacker would try first: common passwords
+//! and words (also reversed, capitalized or with substitutions such as `@`
+//! for `a`), keyboard walks, sequences, repeats and dates. Whatever no
+//! pattern covers is counted as random characters. The estimate is the
+//! number of guesses needed for the cheapest way of covering the whole
+//! password, turned into a score from 0 to 4.
+//!
+//! The dictionaries are the common password list in
+//! `wordlists/common-passwords.txt`, ranked by popularity, and the
+//! passphrase wordlist.
+
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const COMMON_PASSWORDS: &str = include_str!("../wordlists/common-passwords.txt");
+const WORDS: &str = include_str!("../wordlists/eff_large_wordlist.txt");
+
+/// Only this many characters are analysed. The rest count as random.
+const MAX_ANALYSED: usize = 100;
+const MAX_WORD_LEN: usize = 20;
+
+const BRUTEFORCE_CARDINALITY: f64 = 10.0;
+const MIN_GUESSES_SINGLE_CHAR: f64 = 10.0;
+const MIN_GUESSES_MULTI_CHAR: f64 = 50.0;
+/// Guesses charged for every pattern after the first, so that gluing many
+/// short patterns together is not rated as weak as one pattern.
+const MIN_GUESSES_PER_EXTRA_PATTERN: f64 = 10_000.0;
+const MIN_YEAR_SPACE: f64 = 20.0;
+
+/// Keys a keyboard walk can start on, and the average number of neighbours.
+const KEYBOARD_KEYS: f64 = 47.0;
+const KEYBOARD_DEGREE: f64 = 4.6;
+const KEYBOARD_ROWS: [&str; 4] = ["`1234567890-=", "qwertyuiop[]\\", "asdfghjkl;'", "zxcvbnm,./"];
+const KEYBOARD_SHIFTED_ROWS: [&str; 4] = ["~!@#$%^&*()_+", "QWERTYUIOP{}|", "ASDFGHJKL:\"", "ZXCVBNM<>?"];
+
+const L33T: [(char, char); 12] = [
+    ('4', 'a'), ('@', 'a'), ('8', 'b'), ('3', 'e'), ('6', 'g'), ('9', 'g'),
+    ('0', 'o'), ('$', 's'), ('5', 's'), ('7', 't'), ('+', 't'), ('2', 'z'),
+];
+/// `1`, `!` and `|` stand for either `i` or `l`.
+const L33T_I_OR_L: [char; 3] = ['1', '!', '|'];
+
+pub const MAX_SCORE: u8 = 4;
+pub const SCORE_NAMES: [&str; 5] = ["very weak", "weak", "fair", "strong", "very strong"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Pattern {
+    Dictionary { rank: usize, common: bool, reversed: bool, l33t: bool, uppercase: bool },
+    Spatial { turns: usize },
+    Repeat { single_char: bool },
+    Sequence,
+    Date,
+    Bruteforce,
+}
+
+#[derive(Clone, Debug)]
+struct Match {
+    start: usize,
+    end: usize,
+    guesses: f64,
+    pattern: Pattern,
+}
+
+/// Estimated strength of a password.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Estimate {
+    /// Base 10 logarithm of the estimated number of guesses.
+    pub guesses_log10: f64,
+    /// 0 (very weak) to 4 (very strong).
+    pub score: u8,
+    pub warning: Option<&'static str>,
+    pub suggestions: Vec<&'static str>,
+}
+
+impl Estimate {
+    pub fn label(&self) -> &'static str {
+        SCORE_NAMES[self.score as usize]
+    }
+}
+
+/// Estimates the strength of `password`.
+pub fn estimate(password: &str) -> Estimate {
+    let chars: Vec<char> = password.chars().collect();
+    if chars.is_empty() {
+        return Estimate {
+            guesses_log10: 0.0,
+            score: 0,
+            warning: None,
+            suggestions: vec!["Use a few words, avoid common phrases", "No need for symbols, digits, or uppercase letters"],
+        };
+    }
+    let analysed = &chars[..chars.len().min(MAX_ANALYSED)];
+    let (log10, sequence) = most_guessable(analysed);
+    let guesses_log10 = log10 + (chars.len() - analysed.len()) as f64 * BRUTEFORCE_CARDINALITY.log10();
+    let score = match guesses_log10 {
+        g if g < 3.0 => 0,
+        g if g < 6.0 => 1,
+        g if g < 8.0 => 2,
+        g if g < 10.0 => 3,
+        _ => 4,
+    };
+    let (warning, suggestions) = feedback(score, &sequence, analysed.len());
+    Estimate { guesses_log10, score, warning, suggestions }
+}
+
+/// Words and common passwords, mapped to their rank and whether they come
+/// from the common password list.
+fn dictionary() -> &'static HashMap<&'static str, (usize, bool)> {
+    static DICTIONARY: OnceLock<HashMap<&'static str, (usize, bool)>> = OnceLock::new();
+    DICTIONARY.get_or_init(|| {
+        let words = WORDS.lines().count();
+        let mut dictionary: HashMap<&str, (usize, bool)> = WORDS.lines().map(|word| (word, (words, false))).collect();
+        for (i, password) in COMMON_PASSWORDS.lines().enumerate() {
+            dictionary.insert(password, (i + 1, true));
+        }
+        dictionary
+    })
+}
+
+fn binomial(n: usize, k: usize) -> f64 {
+    (0..k).map(|i| (n - i) as f64 / (i + 1) as f64).product()
+}
+
+/// Ways of mixing `a` changed and `b` unchanged characters, with at least one
+/// of each. Used for capitalization, substitutions and shifted keys.
+fn variations(a: usize, b: usize) -> f64 {
+    if a == 0 {
+        1.0
+    } else if b == 0 {
+        2.0
+    } else {
+        (1..=a.min(b)).map(|i| binomial(a + b, i)).sum()
+    }
+}
+
+fn uppercase_variations(token: &[char]) -> f64 {
+    let upper = token.iter().filter(|c| c.is_uppercase()).count();
+    let lower = token.iter().filter(|c| c.is_lowercase()).count();
+    let first_only = upper == 1 && token[0].is_uppercase();
+    let last_only = upper == 1 && token[token.len() - 1].is_uppercase();
+    if upper == 0 {
+        1.0
+    } else if lower == 0 || first_only || last_only {
+        2.0
+    } else {
+        variations(upper, lower)
+    }
+}
+
+fn lowercase(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+fn dictionary_matches(chars: &[char], matches: &mut Vec<Match>) {
+    let dictionary = dictionary();
+    let lower: Vec<char> = chars.iter().map(|&c| lowercase(c)).collect();
+    for start in 0..chars.len() {
+        for end in start + 3..=chars.len().min(start + MAX_WORD_LEN) {
+            let token = &lower[start..end];
+            let uppercase = uppercase_variations(&chars[start..end]);
+            let word: String = token.iter().collect();
+            let reversed: String = token.iter().rev().collect();
+            let mut push = |rank: usize, common: bool, reversed: bool, l33t: f64| {
+                let guesses = rank as f64 * uppercase * l33t * if reversed { 2.0 } else { 1.0 };
+                let pattern = Pattern::Dictionary { rank, common, reversed, l33t: l33t > 1.0, uppercase: uppercase > 1.0 };
+                matches.push(Match { start, end, guesses, pattern });
+            };
+            if let Some(&(rank, common)) = dictionary.get(word.as_str()) {
+                push(rank, common, false, 1.0);
+            }
+            if reversed != word {
+                if let Some(&(rank, common)) = dictionary.get(reversed.as_str()) {
+                    push(rank, common, true, 1.0);
+                }
+            }
+            for i_or_l in ['i', 'l'] {
+                let unl33t: String = token
+                    .iter()
+                    .map(|&c| match L33T.iter().find(|(from, _)| *from == c) {
+                        Some(&(_, to)) => to,
+                        None if L33T_I_OR_L.contains(&c) => i_or_l,
+                        None => c,
+                    })
+                    .collect();
+                if unl33t == word {
+                    break;
+                }
+                if let Some(&(rank, common)) = dictionary.get(unl33t.as_str()) {
+                    let subbed = token.iter().zip(unl33t.chars()).filter(|(a, b)| **a != *b).count();
+                    let unsubbed = unl33t.chars().filter(|c| token.contains(c)).count();
+                    push(rank, common, false, variations(subbed, unsubbed));
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Row, column and whether shift is held, for a key on a QWERTY keyboard.
+fn key_position(c: char) -> Option<(i32, i32, bool)> {
+    for (rows, shifted) in [(KEYBOARD_ROWS, false), (KEYBOARD_SHIFTED_ROWS, true)] {
+        for (row, keys) in rows.iter().enumerate() {
+            if let Some(column) = keys.chars().position(|key| key == c) {
+                return Some((row as i32, column as i32, shifted));
+            }
+        }
+    }
+    None
+}
+
+/// Direction from one key to a neighbouring key, or `None` if they are not
+/// neighbours. Each row is shifted half a key to the right of the one below.
+fn key_direction(from: char, to: char) -> Option<(i32, i32)> {
+    let (r1, c1, _) = key_position(from)?;
+    let (r2, c2, _) = key_position(to)?;
+    let direction = (r2 - r1, c2 - c1);
+    matches!(direction, (0, -1) | (0, 1) | (-1, 0) | (-1, 1) | (1, 0) | (1, -1)).then_some(direction)
+}
+
+fn spatial_matches(chars: &[char], matches: &mut Vec<Match>) {
+    let mut start = 0;
+    while start + 2 < chars.len() {
+        let mut end = start + 1;
+        let mut turns = 0;
+        let mut last = None;
+        while end < chars.len() {
+            let Some(direction) = key_direction(chars[end - 1], chars[end]) else { break };
+            if last != Some(direction) {
+                turns += 1;
+                last = Some(direction);
+            }
+            end += 1;
+        }
+        let len = end - start;
+        if len >= 3 {
+            let mut guesses = 0.0;
+            for i in 2..=len {
+                for j in 1..=turns.min(i - 1) {
+                    guesses += binomial(i - 1, j - 1) * KEYBOARD_KEYS * KEYBOARD_DEGREE.powi(j as i32);
+                }
+            }
+            let shifted = chars[start..end].iter().filter(|&&c| key_position(c).is_some_and(|(_, _, shifted)| shifted)).count();
+            guesses *= variations(shifted, len - shifted);
+            matches.push(Match { start, end, guesses, pattern: Pattern::Spatial { turns } });
+            start = end - 1;
+        } else {
+            start += 1;
+        }
+    }
+}
+
+fn sequence_matches(chars: &[char], matches: &mut Vec<Match>) {
+    let mut start = 0;
+    while start + 2 < chars.len() {
+        let delta = chars[start + 1] as i64 - chars[start] as i64;
+        let mut end = start + 1;
+        while end < chars.len() && chars[end] as i64 - chars[end - 1] as i64 == delta {
+            end += 1;
+        }
+        if (1..=5).contains(&delta.abs()) && end - start >= 3 {
+            let first = chars[start];
+            let base = if "aAzZ019".contains(first) {
+                4.0
+            } else if first.is_ascii_digit() {
+                10.0
+            } else {
+                26.0
+            };
+            let guesses = base * (end - start) as f64 * if delta < 0 { 2.0 } else { 1.0 };
+            matches.push(Match { start, end, guesses, pattern: Pattern::Sequence });
+            start = end - 1;
+        } else {
+            start += 1;
+        }
+    }
+}
+
+fn repeat_matches(chars: &[char], matches: &mut Vec<Match>) {
+    let mut start = 0;
+    while start < chars.len() {
+        // The unit covering the most characters, preferring shorter units.
+        let mut best: Option<(usize, usize)> = None;
+        for unit in 1..=(chars.len() - start) / 2 {
+            let repeats = (1..)
+                .take_while(|k| {
+                    let next = start + k * unit;
+                    next + unit <= chars.len() && chars[next..next + unit] == chars[start..start + unit]
+                })
+                .count()
+                + 1;
+            if repeats >= 2 && best.is_none_or(|(u, r)| unit * repeats > u * r) {
+                best = Some((unit, repeats));
+            }
+        }
+        match best {
+            Some((unit, repeats)) => {
+                let (base, _) = most_guessable(&chars[start..start + unit]);
+                let end = start + unit * repeats;
+                let guesses = 10f64.powf(base) * repeats as f64;
+                matches.push(Match { start, end, guesses, pattern: Pattern::Repeat { single_char: unit == 1 } });
+                start = end;
+            }
+            None => start += 1,
+        }
+    }
+}
+
+fn reference_year() -> i64 {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+    1970 + (secs / 31_556_952) as i64
+}
+
+/// Reads a day, month and year out of three groups of digits, in any of
+/// the usual orders. Two digit years are taken to be 1950 to 2049.
+fn parse_date(parts: [&str; 3]) -> Option<i64> {
+    let number = |part: &str| part.parse::<i64>().ok();
+    let year = |part: &str| match part.len() {
+        2 => number(part).map(|y| if y >= 50 { 1900 + y } else { 2000 + y }),
+        4 => number(part).filter(|y| (1000..=2050).contains(y)),
+        _ => None,
+    };
+    let day_month = |day: &str, month: &str| {
+        day.len() <= 2 && month.len() <= 2 && number(day).is_some_and(|d| (1..=31).contains(&d)) && number(month).is_some_and(|m| (1..=12).contains(&m))
+    };
+    let [a, b, c] = parts;
+    if day_month(a, b) || day_month(b, a) {
+        if let Some(year) = year(c) {
+            return Some(year);
+        }
+    }
+    if day_month(b, c) || day_month(c, b) {
+        return year(a);
+    }
+    None
+}
+
+fn date_matches(chars: &[char], matches: &mut Vec<Match>) {
+    let reference = reference_year();
+    let year_space = |year: i64| ((year - reference).abs() as f64).max(MIN_YEAR_SPACE);
+    for start in 0..chars.len() {
+        for end in start + 4..=chars.len().min(start + 10) {
+            let token: String = chars[start..end].iter().collect();
+            let mut years = Vec::new();
+            if token.bytes().all(|b| b.is_ascii_digit()) {
+                if token.len() == 4 {
+                    if let Some(year) = token.parse::<i64>().ok().filter(|y| (1900..=2050).contains(y)) {
+                        matches.push(Match { start, end, guesses: year_space(year), pattern: Pattern::Date });
+                    }
+                }
+                for i in 1..token.len() - 1 {
+                    for j in i + 1..token.len() {
+                        years.extend(parse_date([&token[..i], &token[i..j], &token[j..]]).map(|year| (year, 1.0)));
+                    }
+                }
+            } else if let Some(separator) = token.chars().find(|c| "/\\_.- ".contains(*c)) {
+                let parts: Vec<&str> = token.split(separator).collect();
+                if let [a, b, c] = parts[..] {
+                    if [a, b, c].iter().all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit())) {
+                        years.extend(parse_date([a, b, c]).map(|year| (year, 4.0)));
+                    }
+                }
+            }
+            if let Some(&(year, separator)) = years.iter().min_by_key(|(year, _)| (year - reference).abs()) {
+                let guesses = 365.0 * year_space(year) * separator;
+                matches.push(Match { start, end, guesses, pattern: Pattern::Date });
+            }
+        }
+    }
+}
+
+/// Last step of a cheapest cover: its log10 guesses, where the last pattern
+/// starts and the index of its match, or `None` for random characters.
+type Step = (f64, usize, Option<usize>);
+
+fn log10_factorial(n: usize) -> f64 {
+    (2..=n).map(|i| (i as f64).log10()).sum()
+}
+
+/// Finds the cheapest way to cover `chars` with matches and random
+/// characters. Returns the base 10 logarithm of the guesses and the
+/// patterns used, in order.
+fn most_guessable(chars: &[char]) -> (f64, Vec<Match>) {
+    let n = chars.len();
+    let mut matches = Vec::new();
+    dictionary_matches(chars, &mut matches);
+    spatial_matches(chars, &mut matches);
+    sequence_matches(chars, &mut matches);
+    repeat_matches(chars, &mut matches);
+    date_matches(chars, &mut matches);
+    for m in &mut matches {
+        // A pattern shorter than the password is never cheaper than this.
+        let min = if m.end - m.start == 1 { MIN_GUESSES_SINGLE_CHAR } else { MIN_GUESSES_MULTI_CHAR };
+        if m.end - m.start < n {
+            m.guesses = m.guesses.max(min);
+        }
+    }
+
+    // best[k][l]: lowest log10 product of guesses of l patterns covering
+    // the first k characters.
+    let mut best: Vec<Vec<Option<Step>>> = vec![vec![None; n + 1]; n + 1];
+    best[0][0] = Some((0.0, 0, None));
+    for end in 1..=n {
+        for count in 1..=end {
+            let mut candidate: Option<Step> = None;
+            let mut consider = |log10: f64, start: usize, index: Option<usize>| {
+                if candidate.is_none_or(|(best, _, _)| log10 < best) {
+                    candidate = Some((log10, start, index));
+                }
+            };
+            for (index, m) in matches.iter().enumerate().filter(|(_, m)| m.end == end) {
+                if let Some((log10, _, _)) = best[m.start][count - 1] {
+                    consider(log10 + m.guesses.log10(), m.start, Some(index));
+                }
+            }
+            for (start, row) in best.iter().enumerate().take(end) {
+                if let Some((log10, _, _)) = row[count - 1] {
+                    let len = end - start;
+                    let min = if len == 1 { MIN_GUESSES_SINGLE_CHAR + 1.0 } else { MIN_GUESSES_MULTI_CHAR + 1.0 };
+                    let guesses = (len as f64 * BRUTEFORCE_CARDINALITY.log10()).max(min.log10());
+                    consider(log10 + guesses, start, None);
+                }
+            }
+            best[end][count] = candidate;
+        }
+    }
+
+    // l! * product + MIN_GUESSES_PER_EXTRA_PATTERN^(l - 1), in logarithms.
+    let total = |count: usize, log10: f64| {
+        let a = log10_factorial(count) + log10;
+        let b = (count - 1) as f64 * MIN_GUESSES_PER_EXTRA_PATTERN.log10();
+        a.max(b) + (1.0 + 10f64.powf(a.min(b) - a.max(b))).log10()
+    };
+    let Some((count, log10)) = (1..=n)
+        .filter_map(|count| best[n][count].map(|(log10, _, _)| (count, total(count, log10))))
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+    else {
+        return (0.0, Vec::new());
+    };
+
+    let mut sequence = Vec::new();
+    let (mut end, mut count) = (n, count);
+    while end > 0 {
+        let (_, start, index) = best[end][count].expect("path was computed");
+        sequence.push(match index {
+            Some(index) => matches[index].clone(),
+            None => Match { start, end, guesses: 0.0, pattern: Pattern::Bruteforce },
+        });
+        end = start;
+        count -= 1;
+    }
+    sequence.reverse();
+    (log10, sequence)
+}
+
+fn feedback(score: u8, sequence: &[Match], len: usize) -> (Option<&'static str>, Vec<&'static str>) {
+    if score > 2 {
+        return (None, Vec::new());
+    }
+    let mut suggestions = vec!["Add another word or two. Uncommon words are better."];
+    let Some(longest) = sequence.iter().max_by_key(|m| m.end - m.start) else {
+        return (None, suggestions);
+    };
+    let warning = match longest.pattern {
+        Pattern::Dictionary { rank, common, reversed, l33t, uppercase } => {
+            if uppercase {
+                suggestions.push("Capitalization doesn't help very much");
+            }
+            if reversed {
+                suggestions.push("Reversed words aren't much harder to guess");
+            }
+            if l33t {
+                suggestions.push("Predictable substitutions like '@' instead of 'a' don't help very much");
+            }
+            let whole = longest.end - longest.start == len;
+            match (common, whole) {
+                (true, true) if rank <= 10 => Some("This is a top-10 common password"),
+                (true, true) if rank <= 100 => Some("This is a top-100 common password"),
+                (true, _) => Some("This is similar to a commonly used password"),
+                (false, true) => Some("A word by itself is easy to guess"),
+                (false, false) => None,
+            }
+        }
+        Pattern::Spatial { turns } => {
+            suggestions.push("Use a longer keyboard pattern with more turns");
+            Some(if turns == 1 { "Straight rows of keys are easy to guess" } else { "Short keyboard patterns are easy to guess" })
+        }
+        Pattern::Repeat { single_char } => {
+            suggestions.push("Avoid repeated words and characters");
+            Some(if single_char {
+                "Repeats like \"aaa\" are easy to guess"
+            } else {
+                "Repeats like \"abcabcabc\" are only slightly harder to guess than \"abc\""
+            })
+        }
+        Pattern::Sequence => {
+            suggestions.push("Avoid sequences");
+            Some("Sequences like abc or 6543 are easy to guess")
+        }
+        Pattern::Date => {
+            suggestions.push("Avoid dates and years that are associated with you");
+            Some("Dates are often easy to guess")
+        }
+        Pattern::Bruteforce => None,
+    };
+    (warning, suggestions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patterns(password: &str) -> Vec<Pattern> {
+        let chars: Vec<char> = password.chars().collect();
+        most_guessable(&chars).1.into_iter().map(|m| m.pattern).collect()
+    }
+
+    #[test]
+    fn test_scores() {
+        for weak in ["", "password", "123456", "qwerty", "P@ssw0rd", "drowssap", "aaaaaaaa", "abcdefgh", "01/02/1990", "asdfghjkl", "abcabcabc"] {
+            assert!(estimate(weak).score <= 1, "{} scored {:?}", weak, estimate(weak));
+        }
+        for strong in ["correct-horse-battery-staple", "blood-ensure-goose-cargo-gate-waste", "xK9#mQ2pL!v7Rt", "tB8$wq!Lz0pN3&yX"] {
+            assert!(estimate(strong).score >= 3, "{} scored {:?}", strong, estimate(strong));
+        }
+        assert!(estimate("Tr0ub4dour&3").score >= 2);
+        assert!(estimate("password").guesses_log10 < estimate("monkey").guesses_log10);
+    }
+
+    #[test]
+    fn test_patterns() {
+        assert!(matches!(patterns("password")[..], [Pattern::Dictionary { rank: 2, common: true, .. }]));
+        assert!(matches!(patterns("P@ssw0rd")[..], [Pattern::Dictionary { l33t: true, uppercase: true, .. }]));
+        assert!(matches!(patterns("drowssap")[..], [Pattern::Dictionary { reversed: true, .. }]));
+        assert!(matches!(patterns("zxcvfr")[..], [Pattern::Spatial { turns: 2 }]));
+        assert!(matches!(patterns("hgfedcba")[..], [Pattern::Sequence]));
+        assert!(matches!(patterns("zzzzzzzz")[..], [Pattern::Repeat { single_char: true }]));
+        assert!(matches!(patterns("1987")[..], [Pattern::Date]));
+        assert!(matches!(patterns("13.05.1987")[..], [Pattern::Date]));
+        assert!(matches!(patterns("x7#q")[..], [Pattern::Bruteforce]));
+    }
+
+    #[test]
+    fn test_feedback() {
+        let estimate = estimate("password");
+        assert_eq!(estimate.warning, Some("This is a top-10 common password"));
+        assert!(!estimate.suggestions.is_empty());
+        assert_eq!(super::estimate("qwertyuiop").warning, Some("This is a top-100 common password"));
+        assert_eq!(super::estimate("ghjkl").warning, Some("Straight rows of keys are easy to guess"));
+        assert!(super::estimate("blood-ensure-goose-cargo-gate-waste").suggestions.is_empty());
+    }
+}
@@ -0,0 +1,143 @@
+//! Named vaults. Each vault is a separate sled database, with its own master
+//! password, in a directory named after the vault under `rustpass/vaults` in
+//! the XDG data directory.
+
+use std::path::{Path, PathBuf};
+
+use sled::Db;
+
+use crate::error::Error;
+use crate::sleddb;
+
+/// Vault used when no name is given and none has been used before.
+pub const DEFAULT_VAULT: &str = "default";
+
+const MAX_NAME_LEN: usize = 64;
+
+pub struct Vaults {
+    dir: PathBuf,
+}
+
+impl Vaults {
+    pub fn new(dir: PathBuf) -> Self {
+        Vaults { dir }
+    }
+
+    /// Vaults in the XDG data directory, or `None` if there is none.
+    pub fn in_data_dir() -> Option<Self> {
+        dirs::data_dir().map(|dir| Vaults::new(dir.join("rustpass").join("vaults")))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn path(&self, name: &str) -> Result<PathBuf, Error> {
+        validate_name(name)?;
+        Ok(self.dir.join(name))
+    }
+
+    pub fn exists(&self, name: &str) -> Result<bool, Error> {
+        Ok(self.path(name)?.is_dir())
+    }
+
+    /// Names of all vaults, sorted.
+    pub fn list(&self) -> Result<Vec<String>, Error> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut names = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            if let Some(name) = entry.file_name().to_str().filter(|name| validate_name(name).is_ok()) {
+                names.push(name.to_string());
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Creates an empty vault. Its master password is set on first use.
+    pub fn create(&self, name: &str) -> Result<Db, Error> {
+        if self.exists(name)? {
+            return Err(Error::InvalidInput(format!("vault {} already exists", name)));
+        }
+        sleddb::open(&self.path(name)?)
+    }
+
+    /// Opens an existing vault.
+    pub fn open(&self, name: &str) -> Result<Db, Error> {
+        if !self.exists(name)? {
+            return Err(Error::InvalidInput(format!("no vault named {}", name)));
+        }
+        sleddb::open(&self.path(name)?)
+    }
+
+    pub fn rename(&self, old: &str, new: &str) -> Result<(), Error> {
+        if !self.exists(old)? {
+            return Err(Error::InvalidInput(format!("no vault named {}", old)));
+        }
+        if self.exists(new)? {
+            return Err(Error::InvalidInput(format!("vault {} already exists", new)));
+        }
+        std::fs::rename(self.path(old)?, self.path(new)?)?;
+        Ok(())
+    }
+
+    /// Deletes a vault and every entry in it.
+    pub fn delete(&self, name: &str) -> Result<(), Error> {
+        if !self.exists(name)? {
+            return Err(Error::InvalidInput(format!("no vault named {}", name)));
+        }
+        std::fs::remove_dir_all(self.path(name)?)?;
+        Ok(())
+    }
+}
+
+/// Vault names become directory names, so only letters, digits, `-` and `_`
+/// are allowed.
+fn validate_name(name: &str) -> Result<(), Error> {
+    if name.is_empty() || name.len() > MAX_NAME_LEN {
+        return Err(Error::InvalidInput(format!("vault names must be 1 to {} characters", MAX_NAME_LEN)));
+    }
+    if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err(Error::InvalidInput(format!("vault name {} may only contain letters, digits, - and _", name)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vault_management() -> Result<(), Error> {
+        let dir = std::env::temp_dir().join(format!("rustpass-vaults-{}", std::process::id()));
+        let vaults = Vaults::new(dir.clone());
+        assert!(vaults.list()?.is_empty());
+
+        drop(vaults.create("staging")?);
+        drop(vaults.create("personal")?);
+        assert!(matches!(vaults.create("staging"), Err(Error::InvalidInput(_))));
+        assert_eq!(vaults.list()?, ["personal", "staging"]);
+
+        vaults.rename("staging", "production")?;
+        assert_eq!(vaults.list()?, ["personal", "production"]);
+        assert!(matches!(vaults.rename("personal", "production"), Err(Error::InvalidInput(_))));
+        assert!(matches!(vaults.open("staging"), Err(Error::InvalidInput(_))));
+
+        vaults.delete("personal")?;
+        assert_eq!(vaults.list()?, ["production"]);
+
+        for bad in ["", "../etc", "a/b", "with space", "."] {
+            assert!(matches!(vaults.path(bad), Err(Error::InvalidInput(_))), "{:?}", bad);
+        }
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}